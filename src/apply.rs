@@ -0,0 +1,764 @@
+use crate::diff::{diff_lines, Edit};
+use crate::error::{PromptPalError, Result};
+use crate::unpack::{canonical_root, confine, write_changes, FileChange};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Context lines that may be dropped from each end of a hunk that doesn't match as given
+const MAX_FUZZ: usize = 2;
+
+/// One line of a hunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HunkLine {
+    /// A line that must be present and is kept
+    Context(String),
+    /// A line that must be present and is removed
+    Remove(String),
+    /// A line that is added
+    Add(String),
+}
+
+/// A contiguous change to a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub lines: Vec<HunkLine>,
+    /// 1-based line the change starts at in the original file, from a `@@` header
+    pub old_start: Option<usize>,
+}
+
+impl Hunk {
+    /// Lines the hunk expects to find in the file
+    fn old_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+            HunkLine::Add(_) => None,
+        })
+    }
+
+    /// Lines the file holds once the hunk is applied
+    fn new_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            HunkLine::Context(text) | HunkLine::Add(text) => Some(text.as_str()),
+            HunkLine::Remove(_) => None,
+        })
+    }
+}
+
+/// The hunks of a response that change one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    /// Path as given in the response; empty if the response didn't name the file
+    pub path: String,
+    pub hunks: Vec<Hunk>,
+    /// True for a unified diff from `/dev/null`, which creates the file
+    pub new_file: bool,
+    /// True for a unified diff to `/dev/null`, which deletes the file
+    pub delete: bool,
+}
+
+/// Parses the unified diffs and SEARCH/REPLACE blocks in an LLM response
+///
+/// Unified diffs start with `--- a/path` and `+++ b/path` lines, as written by
+/// `git diff` and `diff -u`; hunk headers may leave out the line numbers.
+/// SEARCH/REPLACE blocks are preceded by the path of the file they edit:
+///
+/// ````text
+/// src/lib.rs
+/// ```rust
+/// <<<<<<< SEARCH
+/// pub mod cli;
+/// =======
+/// pub mod cli;
+/// pub mod apply;
+/// >>>>>>> REPLACE
+/// ```
+/// ````
+///
+/// Blocks without a path edit the file named by the block before them. Anything
+/// else in the response is ignored.
+pub fn parse_patches(text: &str) -> Vec<Patch> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut patches: Vec<Patch> = Vec::new();
+    // Where the text since the last block starts, for finding a block's path
+    let mut since_block = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some(paths) = diff_header(&lines, i) {
+            let (patch, next) = parse_diff(&lines, i + 2, paths);
+            patches.push(patch);
+            i = next;
+            since_block = next;
+        } else if is_marker(lines[i], '<', "SEARCH") {
+            let path = block_path(&lines[since_block..i])
+                .or_else(|| patches.last().map(|patch| patch.path.clone()))
+                .unwrap_or_default();
+            let (hunk, next) = parse_block(&lines, i + 1);
+            match patches.last_mut() {
+                Some(last) if last.path == path && !last.new_file && !last.delete => {
+                    last.hunks.push(hunk)
+                }
+                _ => patches.push(Patch {
+                    path,
+                    hunks: vec![hunk],
+                    new_file: false,
+                    delete: false,
+                }),
+            }
+            i = next;
+            since_block = next;
+        } else {
+            i += 1;
+        }
+    }
+    patches
+}
+
+/// Returns the old and new paths if a `---`/`+++` header pair starts at line `i`
+fn diff_header<'a>(lines: &[&'a str], i: usize) -> Option<(&'a str, &'a str)> {
+    let old = lines[i].strip_prefix("--- ")?;
+    let new = lines.get(i + 1)?.strip_prefix("+++ ")?;
+    // `diff -u` follows the path with a tab and a timestamp
+    let path = |header: &'a str| header.split('\t').next().unwrap_or(header).trim();
+    Some((path(old), path(new)))
+}
+
+/// Parses the hunks of a unified diff starting at line `i`, returning the patch and the next line
+fn parse_diff(lines: &[&str], mut i: usize, (old, new): (&str, &str)) -> (Patch, usize) {
+    let new_file = old == "/dev/null";
+    let delete = new == "/dev/null";
+    let path = if delete { old } else { new };
+    // Git prefixes the two sides with `a/` and `b/`
+    let git_style = old.starts_with("a/") || new.starts_with("b/");
+    let path = match (
+        git_style,
+        path.strip_prefix("a/").or(path.strip_prefix("b/")),
+    ) {
+        (true, Some(stripped)) => stripped,
+        _ => path,
+    };
+    let mut patch = Patch {
+        path: path.to_string(),
+        hunks: Vec::new(),
+        new_file,
+        delete,
+    };
+
+    while i < lines.len() && lines[i].starts_with("@@") {
+        let counts = hunk_counts(lines[i]);
+        let mut hunk = Hunk {
+            lines: Vec::new(),
+            old_start: counts.map(|(start, _, _)| start),
+        };
+        i += 1;
+        let (mut old_left, mut new_left) =
+            counts.map_or((None, None), |(_, old, new)| (Some(old), Some(new)));
+        while i < lines.len() {
+            let line = lines[i];
+            if old_left == Some(0) && new_left == Some(0) {
+                break;
+            }
+            if counts.is_none() {
+                // Without counts the hunk runs until a line that can't be part of it
+                let next_is_hunk = lines
+                    .get(i + 1)
+                    .is_some_and(|next| next.starts_with([' ', '-', '+']));
+                if line.starts_with("@@")
+                    || diff_header(lines, i).is_some()
+                    || !(line.starts_with([' ', '-', '+', '\\']) || line.is_empty() && next_is_hunk)
+                {
+                    break;
+                }
+            }
+            let text = line.get(1..).unwrap_or_default().to_string();
+            let entry = match line.chars().next() {
+                Some('+') => HunkLine::Add(text),
+                Some('-') => HunkLine::Remove(text),
+                Some('\\') => {
+                    i += 1;
+                    continue;
+                }
+                // An empty line is an unchanged blank line whose space was trimmed
+                Some(' ') | None => HunkLine::Context(text),
+                _ => break,
+            };
+            let (takes_old, takes_new) = match entry {
+                HunkLine::Context(_) => (true, true),
+                HunkLine::Remove(_) => (true, false),
+                HunkLine::Add(_) => (false, true),
+            };
+            for (takes, left) in [(takes_old, &mut old_left), (takes_new, &mut new_left)] {
+                if let (true, Some(left)) = (takes, left) {
+                    *left = left.saturating_sub(1);
+                }
+            }
+            hunk.lines.push(entry);
+            i += 1;
+        }
+        patch.hunks.push(hunk);
+        // Skip trailers such as `\ No newline at end of file`
+        while i < lines.len() && lines[i].starts_with('\\') {
+            i += 1;
+        }
+    }
+    (patch, i)
+}
+
+/// Parses `@@ -12,5 +12,6 @@` into the old start line and the old and new line counts
+fn hunk_counts(header: &str) -> Option<(usize, usize, usize)> {
+    let mut parts = header.trim_start_matches('@').split_whitespace();
+    let range = |part: Option<&str>, sign: char| -> Option<(usize, usize)> {
+        let part = part?.strip_prefix(sign)?;
+        match part.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((part.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(parts.next(), '-')?;
+    let (_, new_count) = range(parts.next(), '+')?;
+    Some((old_start, old_count, new_count))
+}
+
+/// Returns true for a conflict-style marker line such as `<<<<<<< SEARCH`
+fn is_marker(line: &str, marker: char, word: &str) -> bool {
+    let line = line.trim_end();
+    let rest = line.trim_start_matches(marker);
+    (5..=9).contains(&(line.len() - rest.len())) && rest.trim() == word
+}
+
+/// Parses a SEARCH/REPLACE block whose body starts at line `i`, returning the hunk and the next line
+fn parse_block(lines: &[&str], mut i: usize) -> (Hunk, usize) {
+    let mut search = Vec::new();
+    while i < lines.len() && !is_marker(lines[i], '=', "") {
+        search.push(lines[i]);
+        i += 1;
+    }
+    i += 1;
+    let mut replace = Vec::new();
+    while i < lines.len() && !is_marker(lines[i], '>', "REPLACE") {
+        replace.push(lines[i]);
+        i += 1;
+    }
+
+    // Find which lines the block keeps, so they can be matched loosely like diff context
+    let hunk_lines = diff_lines(&search, &replace)
+        .into_iter()
+        .map(|edit| match edit {
+            Edit::Equal { old, .. } => HunkLine::Context(search[old].to_string()),
+            Edit::Delete { old } => HunkLine::Remove(search[old].to_string()),
+            Edit::Insert { new } => HunkLine::Add(replace[new].to_string()),
+        })
+        .collect();
+    let hunk = Hunk {
+        lines: hunk_lines,
+        old_start: None,
+    };
+    (hunk, i + 1)
+}
+
+/// Finds the path named before a SEARCH/REPLACE block, skipping fences and blank lines
+fn block_path(preceding: &[&str]) -> Option<String> {
+    let line = preceding
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with("```"))?;
+    let line = line.strip_prefix("File:").unwrap_or(line);
+    let path = line.trim().trim_matches(['`', '*', ':', '#', ' ']);
+    // A single word that doesn't end a sentence, so "Done." isn't taken for a path
+    let is_path = !path.is_empty()
+        && !path.contains(char::is_whitespace)
+        && !path.ends_with(['.', '!', '?', ',', ')']);
+    is_path.then(|| path.to_string())
+}
+
+/// A hunk that could not be applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HunkFailure {
+    /// Path as given in the response
+    pub path: String,
+    /// 1-based index of the hunk among those for the file
+    pub hunk: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for HunkFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: hunk {}: {}", self.path, self.hunk, self.reason)
+    }
+}
+
+/// The changes the patches in a response make to the files under a root directory
+///
+/// Every hunk is matched against the current files before anything is written;
+/// [`write`](Apply::write) refuses to write anything if one of them failed.
+#[derive(Debug)]
+pub struct Apply {
+    root: PathBuf,
+    pub changes: Vec<FileChange>,
+    pub failures: Vec<HunkFailure>,
+    /// Number of hunks that applied
+    pub applied: usize,
+}
+
+impl Apply {
+    /// Matches every hunk of `patches` against the files under `root`
+    ///
+    /// Hunks are matched exactly first, then ignoring trailing whitespace, then
+    /// ignoring all indentation and spacing, and finally with up to two context
+    /// lines dropped from each end. When a hunk matches in several places, the one
+    /// nearest its `@@` line number wins; without one the hunk fails as ambiguous.
+    ///
+    /// # Returns
+    /// * An [`OutsideRoot`](PromptPalError::OutsideRoot) error if a patch names a
+    ///   file outside `root`
+    /// * A [`Protected`](PromptPalError::Protected) error if a patch names a file
+    ///   inside `.git` or `.promptpal`
+    pub fn plan(root: &Path, patches: Vec<Patch>) -> Result<Self> {
+        let canonical_root = canonical_root(root)?;
+        let mut apply = Apply {
+            root: root.to_path_buf(),
+            changes: Vec::new(),
+            failures: Vec::new(),
+            applied: 0,
+        };
+        // Hunks seen so far for each file, to number them across patches
+        let mut hunk_counts: Vec<(PathBuf, usize)> = Vec::new();
+
+        for patch in patches {
+            let fail = |apply: &mut Apply, hunk: usize, reason: &str| {
+                apply.failures.push(HunkFailure {
+                    path: patch.path.clone(),
+                    hunk,
+                    reason: reason.to_string(),
+                })
+            };
+            if patch.path.is_empty() {
+                fail(&mut apply, 1, "no file path was given before the block");
+                continue;
+            }
+            let path = confine(root, &canonical_root, &patch.path)?;
+            let first_hunk = match hunk_counts.iter_mut().find(|(p, _)| *p == path) {
+                Some((_, count)) => {
+                    *count += patch.hunks.len();
+                    *count - patch.hunks.len() + 1
+                }
+                None => {
+                    hunk_counts.push((path.clone(), patch.hunks.len()));
+                    1
+                }
+            };
+
+            // Later patches to a file apply on top of earlier ones
+            let index = match apply.changes.iter().position(|c| c.path == path) {
+                Some(index) => index,
+                None => {
+                    let target = root.join(&path);
+                    let old = match fs::read_to_string(&target) {
+                        Ok(old) => Some(old),
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                        Err(e) => return Err(PromptPalError::from_io(&target, e)),
+                    };
+                    let new = old.clone().unwrap_or_default();
+                    apply.changes.push(FileChange { path, old, new });
+                    apply.changes.len() - 1
+                }
+            };
+
+            let change = &apply.changes[index];
+            let exists = change.old.is_some() || !change.new.is_empty();
+            if patch.delete {
+                fail(
+                    &mut apply,
+                    first_hunk,
+                    "deleting files is not supported; delete it by hand",
+                );
+                continue;
+            }
+            if patch.new_file && exists && !change.new.is_empty() {
+                fail(&mut apply, first_hunk, "the file to create already exists");
+                continue;
+            }
+
+            let mut content = FileContent::parse(&change.new);
+            let mut offset = 0isize;
+            for (n, hunk) in patch.hunks.iter().enumerate() {
+                let number = first_hunk + n;
+                if !exists && hunk.old_lines().next().is_some() {
+                    fail(&mut apply, number, "file not found");
+                    continue;
+                }
+                let hint = hunk
+                    .old_start
+                    .map(|start| (start as isize - 1 + offset).max(0) as usize);
+                match content.apply(hunk, hint) {
+                    Ok(delta) => {
+                        offset += delta;
+                        apply.applied += 1;
+                    }
+                    Err(reason) => fail(&mut apply, number, &reason),
+                }
+            }
+            apply.changes[index].new = content.render();
+        }
+        apply.changes.retain(|change| {
+            !change.is_unchanged() && (change.old.is_some() || !change.new.is_empty())
+        });
+        Ok(apply)
+    }
+
+    /// Writes the patched files, backing up the files it changes into `backup_dir`
+    ///
+    /// # Returns
+    /// * An error naming the failed hunks, without writing anything, if any hunk failed
+    /// * The paths of the backups made, relative to `backup_dir`
+    pub fn write(&self, backup_dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+        if let Some(failure) = self.failures.first() {
+            return Err(PromptPalError::Region {
+                path: PathBuf::from(&failure.path),
+                message: format!(
+                    "{} hunk(s) failed to apply, so no files were changed",
+                    self.failures.len()
+                ),
+            });
+        }
+        write_changes(&self.root, &self.changes, backup_dir)
+    }
+}
+
+/// A file's lines, with enough about its layout to write it back the same way
+struct FileContent {
+    lines: Vec<String>,
+    crlf: bool,
+    trailing_newline: bool,
+}
+
+impl FileContent {
+    fn parse(content: &str) -> Self {
+        FileContent {
+            lines: content.lines().map(str::to_string).collect(),
+            crlf: content.contains("\r\n"),
+            trailing_newline: content.is_empty() || content.ends_with('\n'),
+        }
+    }
+
+    fn render(&self) -> String {
+        if self.lines.is_empty() {
+            return String::new();
+        }
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        let mut content = self.lines.join(newline);
+        if self.trailing_newline {
+            content.push_str(newline);
+        }
+        content
+    }
+
+    /// Applies `hunk` near 0-based line `hint`, returning how many lines it added
+    fn apply(&mut self, hunk: &Hunk, hint: Option<usize>) -> std::result::Result<isize, String> {
+        let old: Vec<&str> = hunk.old_lines().collect();
+        if old.is_empty() {
+            // A pure insertion can only be placed by its line number
+            let at = match hint {
+                Some(at) if at <= self.lines.len() => at,
+                None if self.lines.is_empty() => 0,
+                Some(_) => return Err("the line to insert at is past the end of the file".into()),
+                None => return Err("the hunk has no context to locate it by".into()),
+            };
+            // `@@ -5,0` inserts after line 5
+            let at = if hunk.old_start.is_some_and(|start| start > 0) {
+                (at + 1).min(self.lines.len())
+            } else {
+                at
+            };
+            let added: Vec<String> = hunk.new_lines().map(str::to_string).collect();
+            let count = added.len() as isize;
+            self.lines.splice(at..at, added);
+            return Ok(count);
+        }
+
+        let Some((start, lead, trail)) = self.locate(hunk, hint)? else {
+            return Err(self.explain_mismatch(hunk));
+        };
+        let lines = &hunk.lines;
+        let segment = &lines[lead..lines.len() - trail];
+        let mut replacement = Vec::new();
+        let mut position = start;
+        for line in segment {
+            match line {
+                // Keep the file's own text for lines that only matched loosely
+                HunkLine::Context(_) => {
+                    replacement.push(self.lines[position].clone());
+                    position += 1;
+                }
+                HunkLine::Remove(_) => position += 1,
+                HunkLine::Add(text) => replacement.push(text.clone()),
+            }
+        }
+        let removed = position - start;
+        let delta = replacement.len() as isize - removed as isize;
+        self.lines.splice(start..position, replacement);
+        Ok(delta)
+    }
+
+    /// Finds where the hunk's lines are, returning the start line and the number of
+    /// context lines dropped from the front and back to make it match
+    ///
+    /// Fails if the lines appear in several places and there is no hint to choose by.
+    fn locate(
+        &self,
+        hunk: &Hunk,
+        hint: Option<usize>,
+    ) -> std::result::Result<Option<(usize, usize, usize)>, String> {
+        let lines = &hunk.lines;
+        let leading = lines
+            .iter()
+            .take_while(|l| matches!(l, HunkLine::Context(_)))
+            .count();
+        let trailing = lines
+            .iter()
+            .rev()
+            .take_while(|l| matches!(l, HunkLine::Context(_)))
+            .count();
+        let normalizers: [fn(&str) -> String; 3] = [
+            |line| line.to_string(),
+            |line| line.trim_end().to_string(),
+            |line| line.split_whitespace().collect::<Vec<_>>().join(" "),
+        ];
+
+        let mut tried = None;
+        for fuzz in 0..=MAX_FUZZ {
+            let (lead, trail) = (fuzz.min(leading), fuzz.min(trailing));
+            if tried == Some((lead, trail)) {
+                continue;
+            }
+            tried = Some((lead, trail));
+            if lead >= lines.len() - trail {
+                break;
+            }
+            let expected: Vec<&str> = lines[lead..lines.len() - trail]
+                .iter()
+                .filter_map(|line| match line {
+                    HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                    HunkLine::Add(_) => None,
+                })
+                .collect();
+            if expected.is_empty() {
+                break;
+            }
+            for normalize in normalizers {
+                let matches = self.find(&expected, normalize);
+                let hint = hint.map(|hint| hint + lead);
+                match (matches.as_slice(), hint) {
+                    ([], _) => continue,
+                    ([only], _) => return Ok(Some((*only, lead, trail))),
+                    (_, Some(hint)) => {
+                        let nearest = matches
+                            .iter()
+                            .min_by_key(|start| start.abs_diff(hint))
+                            .expect("There are several matches");
+                        return Ok(Some((*nearest, lead, trail)));
+                    }
+                    (_, None) => {
+                        let at: Vec<_> = matches.iter().map(|m| (m + 1).to_string()).collect();
+                        return Err(format!(
+                            "the lines to change appear {} times (at lines {}); add more context",
+                            matches.len(),
+                            at.join(", ")
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the start of every run of lines equal to `expected` after `normalize`
+    fn find(&self, expected: &[&str], normalize: fn(&str) -> String) -> Vec<usize> {
+        if expected.len() > self.lines.len() {
+            return Vec::new();
+        }
+        let expected: Vec<String> = expected.iter().map(|line| normalize(line)).collect();
+        let lines: Vec<String> = self.lines.iter().map(|line| normalize(line)).collect();
+        (0..=lines.len() - expected.len())
+            .filter(|&start| lines[start..start + expected.len()] == expected[..])
+            .collect()
+    }
+
+    /// Describes why a hunk didn't match: already applied, or how close it came
+    fn explain_mismatch(&self, hunk: &Hunk) -> String {
+        let reverse = Hunk {
+            lines: hunk
+                .lines
+                .iter()
+                .map(|line| match line {
+                    HunkLine::Remove(text) => HunkLine::Add(text.clone()),
+                    HunkLine::Add(text) => HunkLine::Remove(text.clone()),
+                    context => context.clone(),
+                })
+                .collect(),
+            old_start: None,
+        };
+        let adds_lines = reverse.old_lines().next().is_some();
+        if adds_lines && !matches!(self.locate(&reverse, None), Ok(None)) {
+            return "the change appears to be applied already".into();
+        }
+
+        let loose = |line: &str| line.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let old: Vec<String> = hunk.old_lines().map(loose).collect();
+        let lines: Vec<String> = self.lines.iter().map(|line| loose(line)).collect();
+        let best = (0..lines.len())
+            .map(|start| {
+                let same = old
+                    .iter()
+                    .zip(&lines[start..])
+                    .filter(|(expected, line)| expected == line)
+                    .count();
+                (same, start)
+            })
+            .max_by_key(|&(same, start)| (same, std::cmp::Reverse(start)));
+        match best {
+            Some((same, start)) if same > 0 => format!(
+                "the lines to change were not found; the closest match, at line {}, has {} of {} lines in common",
+                start + 1,
+                same,
+                old.len()
+            ),
+            _ => format!(
+                "the lines to change were not found (first line: {:?})",
+                old.first().map(String::as_str).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const LIB: &str =
+        "pub mod cli;\npub mod config;\n\nfn helper() {\n    let x = 1;\n    x + 1\n}\n";
+
+    #[test]
+    fn test_parse_unified_diff() {
+        let response = "Here's the fix:\n\n```diff\ndiff --git a/src/lib.rs b/src/lib.rs\n--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -4,3 +4,3 @@ pub mod config;\n fn helper() {\n-    let x = 1;\n+    let x = 2;\n     x + 1\n@@ -9 +9,2 @@\n last\n+more\n```\n\n--- /dev/null\n+++ b/NEW.md\n@@\n+# New\n+\n+text\n";
+        let patches = parse_patches(response);
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0].path, "src/lib.rs");
+        assert_eq!(patches[0].hunks.len(), 2);
+        assert_eq!(patches[0].hunks[0].old_start, Some(4));
+        assert_eq!(
+            patches[0].hunks[0].lines,
+            vec![
+                HunkLine::Context("fn helper() {".into()),
+                HunkLine::Remove("    let x = 1;".into()),
+                HunkLine::Add("    let x = 2;".into()),
+                HunkLine::Context("    x + 1".into()),
+            ]
+        );
+        assert_eq!(patches[0].hunks[1].lines.len(), 2);
+        assert!(patches[1].new_file);
+        assert_eq!(patches[1].path, "NEW.md");
+        assert_eq!(
+            patches[1].hunks[0].new_lines().collect::<Vec<_>>(),
+            vec!["# New", "", "text"]
+        );
+    }
+
+    #[test]
+    fn test_parse_search_replace_blocks() {
+        let response = "Edit `src/lib.rs`:\n\n**src/lib.rs**\n```rust\n<<<<<<< SEARCH\npub mod cli;\n=======\npub mod apply;\npub mod cli;\n>>>>>>> REPLACE\n```\n\n```rust\n<<<<<<< SEARCH\n    let x = 1;\n=======\n    let x = 3;\n>>>>>>> REPLACE\n```\n";
+        let patches = parse_patches(response);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, "src/lib.rs");
+        assert_eq!(patches[0].hunks.len(), 2);
+        assert_eq!(
+            patches[0].hunks[0].lines,
+            vec![
+                HunkLine::Add("pub mod apply;".into()),
+                HunkLine::Context("pub mod cli;".into()),
+            ]
+        );
+        assert!(
+            parse_patches("<<<<<<< SEARCH\na\n=======\nb\n>>>>>>> REPLACE\n")[0]
+                .path
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_matching() {
+        let mut content = FileContent::parse(LIB);
+        let hunk = |lines: Vec<HunkLine>| Hunk {
+            lines,
+            old_start: None,
+        };
+
+        // Indentation and stale context still match; the file's own context is kept
+        let edit = hunk(vec![
+            HunkLine::Context("fn helper()  {".into()),
+            HunkLine::Remove("let x = 1;".into()),
+            HunkLine::Add("    let x = 2;".into()),
+            HunkLine::Context("    x + 1".into()),
+            HunkLine::Context("} // end".into()),
+        ]);
+        assert_eq!(content.apply(&edit, None), Ok(0));
+        assert_eq!(content.render(), LIB.replace("let x = 1", "let x = 2"));
+        assert_eq!(
+            content.apply(&edit, None),
+            Err("the change appears to be applied already".into())
+        );
+
+        let missing = hunk(vec![HunkLine::Remove("pub mod missing;".into())]);
+        assert!(content
+            .apply(&missing, None)
+            .unwrap_err()
+            .contains("not found"));
+
+        let mut repeated = FileContent::parse("a\nx\nb\nx\n");
+        let x = hunk(vec![
+            HunkLine::Remove("x".into()),
+            HunkLine::Add("y".into()),
+        ]);
+        assert!(repeated.apply(&x, None).unwrap_err().contains("2 times"));
+        assert_eq!(repeated.apply(&x, Some(3)), Ok(0));
+        assert_eq!(repeated.render(), "a\nx\nb\ny\n");
+    }
+
+    #[test]
+    fn test_apply_is_atomic() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("lib.rs"), LIB).unwrap();
+        fs::write(root.join("crlf.txt"), "one\r\ntwo\r\n").unwrap();
+
+        let response = "lib.rs\n<<<<<<< SEARCH\npub mod config;\n=======\npub mod config;\npub mod apply;\n>>>>>>> REPLACE\n\ncrlf.txt\n<<<<<<< SEARCH\ntwo\n=======\n2\n>>>>>>> REPLACE\n\nlib.rs\n<<<<<<< SEARCH\nfn nope() {}\n=======\n>>>>>>> REPLACE\n";
+        let apply = Apply::plan(root, parse_patches(response)).unwrap();
+        assert_eq!(apply.applied, 2);
+        assert_eq!(apply.failures.len(), 1);
+        assert_eq!(apply.failures[0].path, "lib.rs");
+        assert_eq!(apply.failures[0].hunk, 2);
+        assert!(apply.write(None).is_err());
+        assert_eq!(fs::read_to_string(root.join("lib.rs")).unwrap(), LIB);
+
+        let response = response.split("\n\nlib.rs").next().unwrap();
+        let apply = Apply::plan(root, parse_patches(response)).unwrap();
+        assert!(apply.failures.is_empty());
+        apply.write(None).unwrap();
+        assert!(fs::read_to_string(root.join("lib.rs"))
+            .unwrap()
+            .contains("pub mod config;\npub mod apply;\n"));
+        assert_eq!(
+            fs::read_to_string(root.join("crlf.txt")).unwrap(),
+            "one\r\n2\r\n"
+        );
+
+        let escape = parse_patches("--- a/../x\n+++ b/../x\n@@ -1 +1 @@\n-a\n+b\n");
+        assert!(matches!(
+            Apply::plan(root, escape),
+            Err(PromptPalError::OutsideRoot { .. })
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::error::{PromptPalError, Result};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::time::Duration;
+
+/// Environment variable the API key is read from unless another is named
+pub const DEFAULT_API_KEY_ENV: &str = "OPENAI_API_KEY";
+
+/// How a chat completion ended, as far as the endpoint said
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Answer {
+    /// `stop` when the model finished, `length` when it ran out of tokens
+    pub finish_reason: Option<String>,
+}
+
+/// Sends prompts to an OpenAI-compatible `/chat/completions` endpoint, such as the
+/// ones llama.cpp, vLLM and Ollama serve
+///
+/// # Example
+/// ```rust,no_run
+/// use promptpal::ask::ChatClient;
+/// # async fn run() -> promptpal::error::Result<()> {
+/// let client = ChatClient::new("http://localhost:8080/v1", "qwen2.5-coder");
+/// client.ask("Explain this code", true, std::io::stdout()).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChatClient {
+    client: reqwest::Client,
+    url: String,
+    model: String,
+    api_key: Option<String>,
+    system: Option<String>,
+    temperature: Option<f32>,
+}
+
+impl ChatClient {
+    /// Creates a client for the API at `endpoint`, the base URL ending in `/v1`
+    ///
+    /// A URL already ending in `/chat/completions` is used as it is.
+    pub fn new(endpoint: &str, model: impl Into<String>) -> Self {
+        let endpoint = endpoint.trim_end_matches('/');
+        let url = if endpoint.ends_with("/chat/completions") {
+            endpoint.to_string()
+        } else {
+            format!("{}/chat/completions", endpoint)
+        };
+        // No overall timeout, since a long answer can stream for minutes
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .build()
+            .expect("HTTP client builds with default TLS settings");
+        ChatClient {
+            client,
+            url,
+            model: model.into(),
+            api_key: None,
+            system: None,
+            temperature: None,
+        }
+    }
+
+    /// Sends `key` as a bearer token
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Sends `message` as a system message before the prompt
+    pub fn system(mut self, message: impl Into<String>) -> Self {
+        self.system = Some(message.into());
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    /// Returns the URL requests are sent to
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Sends `prompt` as the user message and writes the answer to `writer`
+    ///
+    /// With `stream`, the answer is written and flushed piece by piece as it arrives.
+    /// Endpoints that answer a streaming request all at once are handled too.
+    pub async fn ask<W: Write>(&self, prompt: &str, stream: bool, mut writer: W) -> Result<Answer> {
+        let mut messages = Vec::new();
+        if let Some(system) = &self.system {
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+        messages.push(json!({ "role": "user", "content": prompt }));
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": stream,
+        });
+        if let Some(temperature) = self.temperature {
+            body["temperature"] = json!(temperature);
+        }
+
+        let mut request = self.client.post(&self.url).json(&body);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let mut response = request.send().await.map_err(|e| self.error(describe(&e)))?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(self.error(format!("{}: {}", status, error_message(&text))));
+        }
+        let is_event_stream = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+        if !is_event_stream {
+            let text = response
+                .text()
+                .await
+                .map_err(|e| self.error(describe(&e)))?;
+            let value: Value = serde_json::from_str(&text)
+                .map_err(|e| self.error(format!("invalid response: {}", e)))?;
+            let choice = &value["choices"][0];
+            let content = choice["message"]["content"].as_str().unwrap_or_default();
+            self.write(&mut writer, content)?;
+            return Ok(Answer {
+                finish_reason: choice["finish_reason"].as_str().map(String::from),
+            });
+        }
+
+        // Server-sent events: one `data:` line per chunk, until `data: [DONE]`
+        let mut answer = Answer::default();
+        let mut buffer = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| self.error(describe(&e)))?
+        {
+            buffer.extend_from_slice(&chunk);
+            while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+                let data = data.trim();
+                if data == "[DONE]" {
+                    return Ok(answer);
+                }
+                let event: Value = serde_json::from_str(data)
+                    .map_err(|e| self.error(format!("invalid event: {}", e)))?;
+                if event.get("error").is_some() {
+                    return Err(self.error(error_message(data)));
+                }
+                let choice = &event["choices"][0];
+                if let Some(content) = choice["delta"]["content"].as_str() {
+                    self.write(&mut writer, content)?;
+                }
+                if let Some(reason) = choice["finish_reason"].as_str() {
+                    answer.finish_reason = Some(reason.to_string());
+                }
+            }
+        }
+        Ok(answer)
+    }
+
+    fn write(&self, writer: &mut impl Write, content: &str) -> Result<()> {
+        writer
+            .write_all(content.as_bytes())
+            .and_then(|()| writer.flush())
+            .map_err(|source| PromptPalError::Output { source })
+    }
+
+    fn error(&self, message: impl ToString) -> PromptPalError {
+        PromptPalError::Endpoint {
+            url: self.url.clone(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Describes an error along with its causes, which say what actually went wrong
+fn describe(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+/// Picks the message out of an OpenAI-style error body, or returns the body as it is
+fn error_message(body: &str) -> String {
+    let value: Option<Value> = serde_json::from_str(body).ok();
+    let message = value.as_ref().and_then(|value| match &value["error"] {
+        Value::String(message) => Some(message.as_str()),
+        error => error["message"].as_str(),
+    });
+    message.unwrap_or(body.trim()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers one request with `response` and returns the request it got
+    async fn mock(response: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            // Read the headers, then as much body as they announce
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (endpoint, handle)
+    }
+
+    #[tokio::test]
+    async fn test_ask_streams_the_answer() {
+        let (endpoint, server) = mock(concat!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n",
+            ": keep-alive\n\n",
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Looks \"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"good.\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        ))
+        .await;
+        let client = ChatClient::new(&endpoint, "local-model")
+            .api_key("sk-test")
+            .system("Be brief");
+        let mut output = Vec::new();
+        let answer = client.ask("Review this", true, &mut output).await.unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "Looks good.");
+        assert_eq!(answer.finish_reason.as_deref(), Some("stop"));
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /v1/chat/completions HTTP/1.1"));
+        assert!(request.contains("authorization: Bearer sk-test"));
+        let body: Value = serde_json::from_str(request.split_once("\r\n\r\n").unwrap().1).unwrap();
+        assert_eq!(body["model"], "local-model");
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["messages"][0]["content"], "Be brief");
+        assert_eq!(body["messages"][1]["content"], "Review this");
+    }
+
+    #[tokio::test]
+    async fn test_ask_reports_endpoint_errors() {
+        let (endpoint, _server) = mock(concat!(
+            "HTTP/1.1 401 Unauthorized\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n",
+            "{\"error\":{\"message\":\"Incorrect API key provided\"}}",
+        ))
+        .await;
+        let client = ChatClient::new(&format!("{}/", endpoint), "gpt-4o");
+        let error = client.ask("Hi", true, Vec::new()).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Request to {}/chat/completions failed: 401 Unauthorized: Incorrect API key provided",
+                endpoint
+            )
+        );
+    }
+}
@@ -0,0 +1,969 @@
+use crate::cache::Cache;
+use crate::error::{PromptPalError, Result, Warning};
+use crate::fileinfo::{ContentMode, FileInfo};
+use crate::imports::{follow_imports, ImportGraph};
+use crate::lines::number_lines;
+use crate::outline::outline_lines;
+use crate::rank::{Relevance, RelevanceIndex};
+use crate::redact::{Finding, Redaction, RedactionRules, SecretScanner};
+use crate::render::{render, write, OutputFormat, RenderOptions, Transform};
+use crate::report::{FileTokens, TokenReport};
+use crate::spec::FileSpec;
+use crate::strip::{strip_lines, Strip, TokenSavings};
+use crate::tokenizer::{context_size, Encoding};
+use crate::tree::FileNode;
+use crate::walkdir::{scan_directory_filtered, FileFilter};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A rendered prompt together with a report of what went into it
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    /// The rendered prompt text
+    pub text: String,
+    /// Structured report describing the prompt
+    pub report: PromptReport,
+}
+
+/// Describes which files were included in a prompt and how many tokens they use
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptReport {
+    /// Files included in the prompt, in output order
+    pub files: Vec<FileTokens>,
+    /// Files left out because they did not fit in the token budget or the top-k cut
+    pub skipped: Vec<FileTokens>,
+    /// Relevance of each included file to the instructions, when ranking is on
+    pub relevance: Vec<Relevance>,
+    /// Tokens saved in each included file by [`strip`](PromptBuilder::strip)
+    pub savings: Vec<TokenSavings>,
+    /// Included files rendered as outlines (signatures and doc comments only)
+    pub outlined: Vec<PathBuf>,
+    /// Import graph of the followed entry files, when following imports
+    pub dependencies: Option<ImportGraph>,
+    /// Files left out because they look like they hold credentials
+    pub sensitive: Vec<PathBuf>,
+    /// Token breakdown of the included files
+    pub tokens: TokenReport,
+    /// Tokens in the rendered prompt, including markup and instructions
+    pub prompt_tokens: usize,
+    /// Secrets replaced with `[REDACTED:kind]` placeholders in the included files
+    pub redactions: Vec<Redaction>,
+    /// Non-fatal problems, such as files that couldn't be read
+    pub warnings: Vec<Warning>,
+}
+
+/// Builds a prompt from one or more directories without going through the CLI
+///
+/// # Example
+/// ```rust
+/// use promptpal::{OutputFormat, PromptBuilder};
+/// # fn main() -> promptpal::error::Result<()> {
+/// let prompt = PromptBuilder::new()
+///     .root("src")
+///     .include("**/*.rs")
+///     .budget(50_000)
+///     .format(OutputFormat::Markdown)
+///     .instructions("Review the error handling")
+///     .build()?;
+/// println!("{} tokens", prompt.report.prompt_tokens);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PromptBuilder {
+    roots: Vec<PathBuf>,
+    include: Vec<String>,
+    narrow: Vec<Vec<String>>,
+    exclude: Vec<String>,
+    allow_sensitive: bool,
+    encoding: Encoding,
+    model: Option<String>,
+    budget: Option<usize>,
+    fail_on_budget: bool,
+    rank: bool,
+    top_k: Option<usize>,
+    entries: Vec<PathBuf>,
+    files: Vec<FileSpec>,
+    import_depth: Option<usize>,
+    emit_dependencies: bool,
+    outline: Vec<String>,
+    outline_fallback: bool,
+    processor: ContentProcessor,
+    fail_on_secrets: bool,
+    format: OutputFormat,
+    instructions: String,
+    options: RenderOptions,
+    top_files: usize,
+}
+
+impl Default for PromptBuilder {
+    fn default() -> Self {
+        PromptBuilder {
+            roots: Vec::new(),
+            include: Vec::new(),
+            narrow: Vec::new(),
+            exclude: Vec::new(),
+            allow_sensitive: false,
+            encoding: Encoding::default(),
+            model: None,
+            budget: None,
+            fail_on_budget: false,
+            rank: false,
+            top_k: None,
+            entries: Vec::new(),
+            files: Vec::new(),
+            import_depth: None,
+            emit_dependencies: false,
+            outline: Vec::new(),
+            outline_fallback: false,
+            processor: ContentProcessor::default(),
+            fail_on_secrets: false,
+            format: OutputFormat::default(),
+            instructions: String::new(),
+            options: RenderOptions::default(),
+            top_files: 10,
+        }
+    }
+}
+
+impl PromptBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory to scan
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.roots.push(root.into());
+        self
+    }
+
+    /// Adds a glob pattern files must match, relative to their root
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Also requires files to match one of `patterns`, on top of the include globs
+    ///
+    /// Unlike [`include`](Self::include), which widens the selection, each call narrows it.
+    pub fn narrow<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let patterns: Vec<String> = patterns.into_iter().map(Into::into).collect();
+        if !patterns.is_empty() {
+            self.narrow.push(patterns);
+        }
+        self
+    }
+
+    /// Adds a glob pattern for files and directories to leave out, relative to their root
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Leaves out a single file, such as the redaction rules or the prompt being written,
+    /// if it is under one of the roots added so far
+    ///
+    /// The file need not exist yet, but its directory must.
+    pub fn exclude_file(mut self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return self;
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let Ok(dir) = dir.canonicalize() else {
+            return self;
+        };
+        let relative: Vec<_> = self
+            .roots
+            .iter()
+            .filter_map(|root| Some(dir.strip_prefix(root.canonicalize().ok()?).ok()?.join(name)))
+            .collect();
+        for relative in relative {
+            self = self.exclude(globset::escape(&relative.to_string_lossy()));
+        }
+        self
+    }
+
+    /// Includes files on the sensitive denylist, such as `.env` files and private keys
+    pub fn allow_sensitive(mut self, allow: bool) -> Self {
+        self.allow_sensitive = allow;
+        self
+    }
+
+    /// Sets the encoding used to count tokens
+    pub fn tokenizer(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Reuses token counts, outlines and secret scans of content seen before from
+    /// `cache`, and adds new ones to it
+    ///
+    /// The cache isn't saved; call [`Cache::save`] once the prompt is built.
+    pub fn cache(mut self, cache: Arc<Cache>) -> Self {
+        self.processor.cache = Some(cache);
+        self
+    }
+
+    /// Sets the model whose context window the report is measured against
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// Limits the total tokens of included file contents; files that don't fit are skipped
+    pub fn budget(mut self, max_tokens: usize) -> Self {
+        self.budget = Some(max_tokens);
+        self
+    }
+
+    /// Fails with [`PromptPalError::BudgetExceeded`] instead of skipping files over budget
+    pub fn fail_on_budget(mut self, fail: bool) -> Self {
+        self.fail_on_budget = fail;
+        self
+    }
+
+    /// Picks files by BM25 relevance to the instructions, most relevant first
+    ///
+    /// The budget is then filled with the most relevant files rather than in path order.
+    /// Rendered output still follows the directory tree.
+    pub fn rank_by_relevance(mut self, rank: bool) -> Self {
+        self.rank = rank;
+        self
+    }
+
+    /// Keeps only the `k` files most relevant to the instructions; turns ranking on
+    pub fn top_k(mut self, k: usize) -> Self {
+        self.rank = true;
+        self.top_k = Some(k);
+        self
+    }
+
+    /// Limits the prompt to `entry` and the files it imports, directly or indirectly
+    ///
+    /// `entry` is relative to a root, or prefixed with it. Can be called more than once.
+    /// Rust `mod`/`use`, Python `import`, JS/TS `import`/`require`, Go imports and
+    /// C `#include "..."` are followed to files in the scanned tree.
+    pub fn follow_imports(mut self, entry: impl Into<PathBuf>) -> Self {
+        self.entries.push(entry.into());
+        self
+    }
+
+    /// Limits the prompt to a listed file, a directory, or a region of a file
+    ///
+    /// Paths are relative to a root, or prefixed with it. Can be called more than once;
+    /// combined with [`follow_imports`](Self::follow_imports), both sets are included.
+    /// A region renders with its original line numbers and a `range` attribute.
+    pub fn file(mut self, spec: FileSpec) -> Self {
+        self.files.push(spec);
+        self
+    }
+
+    /// Limits how many import hops are followed from the entries (unlimited by default)
+    pub fn import_depth(mut self, depth: usize) -> Self {
+        self.import_depth = Some(depth);
+        self
+    }
+
+    /// Emits the import graph as a dependencies section after the tree view
+    pub fn dependencies(mut self, emit: bool) -> Self {
+        self.emit_dependencies = emit;
+        self
+    }
+
+    /// Renders files matching this glob as outlines: signatures and doc comments, no bodies
+    ///
+    /// Supported for Rust, Python, TS/JS and Go; other files matching it are kept in full.
+    pub fn outline(mut self, pattern: impl Into<String>) -> Self {
+        self.outline.push(pattern.into());
+        self
+    }
+
+    /// Includes the outline of a file that doesn't fit in the budget, if the outline fits
+    pub fn outline_fallback(mut self, fallback: bool) -> Self {
+        self.outline_fallback = fallback;
+        self
+    }
+
+    /// Strips comments, blank lines or license headers from file contents to save tokens
+    ///
+    /// Comments are found with a lexer for the file's language, so string literals
+    /// that look like comments are left alone.
+    pub fn strip(mut self, strip: Strip) -> Self {
+        if !self.processor.strip.contains(&strip) {
+            self.processor.strip.push(strip);
+        }
+        self
+    }
+
+    /// Prefixes each content line with its line number in the file
+    ///
+    /// Numbers refer to the file on disk, so they stay right for stripped and
+    /// outlined content. XML content blocks get a `start_line` attribute.
+    pub fn line_numbers(mut self, line_numbers: bool) -> Self {
+        self.processor.line_numbers = line_numbers;
+        self.options.line_numbers = line_numbers;
+        self
+    }
+
+    /// Turns built-in secret redaction on or off (on by default)
+    ///
+    /// Custom rules added with [`redaction_rules`](Self::redaction_rules) still apply when off.
+    pub fn redact(mut self, redact: bool) -> Self {
+        self.processor.scanner = self.processor.scanner.builtin(redact);
+        self
+    }
+
+    /// Adds custom redaction rules, applied to file contents and displayed paths
+    pub fn redaction_rules(mut self, rules: RedactionRules) -> Self {
+        self.processor.scanner = self.processor.scanner.with_rules(rules);
+        self
+    }
+
+    /// Fails with [`PromptPalError::SecretDetected`] instead of redacting secrets
+    pub fn fail_on_secrets(mut self, fail: bool) -> Self {
+        self.fail_on_secrets = fail;
+        self
+    }
+
+    /// Sets the output format
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the instructions placed at the top of the prompt
+    pub fn instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = instructions.into();
+        self
+    }
+
+    /// Annotates the tree view with token counts and sizes
+    pub fn annotate_tree(mut self, annotate: bool) -> Self {
+        self.options.annotate_tree = annotate;
+        self
+    }
+
+    /// Sets how many of the heaviest files are listed in the report
+    pub fn top_files(mut self, top_n: usize) -> Self {
+        self.top_files = top_n;
+        self
+    }
+
+    /// Scans the roots, applies the filters and budget, and renders the prompt
+    pub fn build(&self) -> Result<Prompt> {
+        let selection = self.select(true)?;
+        let text = render(
+            self.format,
+            &selection.files,
+            &self.instructions,
+            &self.render_options(&selection, false),
+        );
+        // The whole prompt is counted once and never cached, since it rarely recurs
+        let prompt_tokens = self.encoding.count(&text);
+
+        Ok(Prompt {
+            text,
+            report: self.report(selection, prompt_tokens),
+        })
+    }
+
+    /// Renders only the tree view of the files the prompt would include
+    pub fn tree(&self) -> Result<String> {
+        let selection = self.select(false)?;
+        let options = self.render_options(&selection, false);
+        Ok(FileNode::build(&selection.files, &options).tree_view(options.annotate_tree))
+    }
+
+    /// Selects the files the prompt would include, with their content as it would appear in it
+    ///
+    /// Content is redacted, stripped, outlined and numbered as in [`build`](Self::build),
+    /// and the budget and top-k cut apply.
+    pub fn files(&self) -> Result<Vec<FileInfo>> {
+        Ok(self.select(true)?.files)
+    }
+
+    /// Measures every file the prompt could include, ignoring the budget and top-k cut
+    ///
+    /// Token counts reflect stripping and outlining, as they would in the prompt.
+    pub fn candidates(&self) -> Result<Vec<FileTokens>> {
+        Ok(self.candidates_with_warnings()?.0)
+    }
+
+    /// Like [`candidates`](Self::candidates), but also returns the problems met along
+    /// the way, such as files that couldn't be read and were left out
+    pub fn candidates_with_warnings(&self) -> Result<(Vec<FileTokens>, Vec<Warning>)> {
+        let unlimited = PromptBuilder {
+            budget: None,
+            fail_on_budget: false,
+            top_k: None,
+            ..self.clone()
+        };
+        let selection = unlimited.select(false)?;
+        let files = selection
+            .files
+            .into_iter()
+            .map(|file| FileTokens {
+                tokens: file.tokens.unwrap_or_default(),
+                path: file.path,
+                size: file.size,
+            })
+            .collect();
+        Ok((files, selection.warnings))
+    }
+
+    /// Renders the prompt incrementally to `writer` instead of building it in memory
+    ///
+    /// Files are read once to count tokens and again just before they're written, so
+    /// peak memory stays around the size of the largest file. The report's
+    /// `prompt_tokens` adds the markup to the files' own counts, so it may differ
+    /// slightly from [`build`](Self::build).
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<PromptReport> {
+        let selection = self.select(false)?;
+        let options = self.render_options(&selection, true);
+        write(
+            &mut writer,
+            self.format,
+            &selection.files,
+            &self.instructions,
+            &options,
+        )
+        .map_err(|source| PromptPalError::Output { source })?;
+
+        // Content was counted when the files were selected; the markup around it is
+        // counted from the same prompt rendered with every file left empty
+        let empty: Vec<_> = selection
+            .files
+            .iter()
+            .map(|file| FileInfo {
+                content: Some(String::new()),
+                ..file.clone()
+            })
+            .collect();
+        let markup = render(
+            self.format,
+            &empty,
+            &self.instructions,
+            &RenderOptions {
+                lazy_content: false,
+                ..options
+            },
+        );
+        let content: usize = selection.files.iter().filter_map(|file| file.tokens).sum();
+        let prompt_tokens = self.encoding.count(&markup) + content;
+        Ok(self.report(selection, prompt_tokens))
+    }
+
+    /// Returns the render options with redaction applied to displayed paths and,
+    /// for lazily loaded files, to their content
+    fn render_options(&self, selection: &Selection, lazy_content: bool) -> RenderOptions {
+        let processor = ContentProcessor {
+            roots: self.roots.clone(),
+            ..self.processor.clone()
+        };
+        RenderOptions {
+            lazy_content,
+            transform: Some(Arc::new(processor)),
+            dependencies: selection
+                .dependencies
+                .clone()
+                .filter(|_| self.emit_dependencies),
+            ..self.options.clone()
+        }
+    }
+
+    /// Returns the matches the redaction rules would replace, without rendering anything
+    ///
+    /// Each [`Redaction`] carries the matched text in `matched`, so rules can be checked
+    /// before they are trusted. Matches of the built-in detectors are real secrets, so
+    /// only their first four characters are kept. Budget and secret failure settings
+    /// are ignored.
+    pub fn redaction_matches(&self) -> Result<Vec<Redaction>> {
+        let processor = ContentProcessor {
+            roots: self.roots.clone(),
+            ..self.processor.clone()
+        };
+        let filter = self.file_filter()?;
+
+        let mut matches = Vec::new();
+        for root in &self.roots {
+            for path in scan_directory_filtered(root, &filter)?.files {
+                let Ok(info) = FileInfo::with_full_content(&path) else {
+                    continue;
+                };
+                let (_, findings) = processor.process(&path, info.content.unwrap_or_default());
+                matches.extend(findings.into_iter().map(|finding| Redaction {
+                    path: path.clone(),
+                    matched: if SecretScanner::is_builtin(&finding.kind) {
+                        let start: String = finding.matched.chars().take(4).collect();
+                        format!("{}…", start)
+                    } else {
+                        finding.matched
+                    },
+                    kind: finding.kind,
+                    line: finding.line,
+                }));
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Scans the roots and picks the files that fit in the budget, keeping their
+    /// content only if `keep_content` is set
+    fn select(&self, keep_content: bool) -> Result<Selection> {
+        let filter = self.file_filter()?;
+
+        let mut paths = Vec::new();
+        let mut sensitive = Vec::new();
+        let mut warnings = Vec::new();
+        let mut dependencies = (!self.entries.is_empty()).then(ImportGraph::default);
+        let mut unresolved = self.entries.clone();
+        let mut unlisted: Vec<_> = self.files.iter().collect();
+        let mut regions = HashMap::new();
+        for root in &self.roots {
+            let scan = scan_directory_filtered(root, &filter)?;
+            sensitive.extend(scan.sensitive);
+            warnings.extend(scan.warnings);
+
+            if dependencies.is_none() && self.files.is_empty() {
+                paths.extend(scan.files);
+                continue;
+            }
+            let mut selected = Vec::new();
+            if let Some(graph) = dependencies.as_mut() {
+                // Each entry is followed within the root it was found in
+                let (entries, rest): (Vec<_>, Vec<_>) = unresolved.into_iter().partition(|entry| {
+                    scan.files.contains(entry) || scan.files.contains(&root.join(entry))
+                });
+                unresolved = rest;
+                if !entries.is_empty() {
+                    let followed = follow_imports(root, &scan.files, &entries, self.import_depth)
+                        .map_err(|missing| Self::not_scanned(&missing[0]))?;
+                    // Closest imports first, so a budget keeps the entries and their direct imports
+                    selected.extend(followed.files.iter().cloned());
+                    graph.files.extend(followed.files);
+                    graph.edges.extend(followed.edges);
+                }
+            }
+
+            // Listed files follow in the order they were given; a directory lists everything in it
+            let mut rest = Vec::new();
+            for spec in unlisted {
+                let under_root = root.join(&spec.path);
+                let matches: Vec<_> = scan
+                    .files
+                    .iter()
+                    .filter(|file| match spec.region {
+                        Some(_) => **file == spec.path || **file == under_root,
+                        None => file.starts_with(&spec.path) || file.starts_with(&under_root),
+                    })
+                    .collect();
+                if matches.is_empty() {
+                    rest.push(spec);
+                    continue;
+                }
+                for file in matches {
+                    if let Some(region) = &spec.region {
+                        if regions.insert(file.clone(), region.clone()).is_some() {
+                            return Err(PromptPalError::Region {
+                                path: file.clone(),
+                                message: "file is selected more than once".into(),
+                            });
+                        }
+                    }
+                    if !selected.contains(file) {
+                        selected.push(file.clone());
+                    }
+                }
+            }
+            unlisted = rest;
+            paths.extend(selected);
+        }
+        if let Some(entry) = unresolved.first() {
+            return Err(Self::not_scanned(entry));
+        }
+        if let Some(spec) = unlisted.first() {
+            return Err(Self::not_scanned(&spec.path));
+        }
+
+        let processor = ContentProcessor {
+            roots: self.roots.clone(),
+            ..self.processor.clone()
+        };
+        let outline_filter = if self.outline.is_empty() {
+            None
+        } else {
+            Some(FileFilter::new(&self.outline, &[])?)
+        };
+        let outline_filter = outline_filter.as_ref();
+        let mut candidates = Vec::new();
+        let mut savings = Vec::new();
+        let mut index = RelevanceIndex::new();
+        for path in paths {
+            let mut info = match FileInfo::with_full_content(&path) {
+                Ok(info) => info,
+                Err(e) => {
+                    warnings.push(Warning::new(path, e.to_string()));
+                    continue;
+                }
+            };
+            if let Some(region) = regions.get(&path) {
+                let content = info.content.as_deref().unwrap_or_default();
+                let range =
+                    region
+                        .resolve(&path, content)
+                        .map_err(|message| PromptPalError::Region {
+                            path: path.clone(),
+                            message,
+                        })?;
+                info.content = Some(range.slice(content).to_string());
+                info.start_line = range.start;
+                info.range = Some(range);
+            }
+            let relative = processor.relative(&path).1;
+            let outline_full = outline_filter.is_some_and(|filter| filter.is_match(relative));
+            // Outline up front when asked to, or keep the outline handy as a budget fallback
+            let want_outline = outline_full || (self.outline_fallback && self.budget.is_some());
+            let content = info.content.take().unwrap_or_default();
+            let prepared = processor.prepare(&info, content, want_outline);
+            if let Some(unstripped) = &prepared.unstripped {
+                let before = self.count(unstripped);
+                let after = self.count(&prepared.content);
+                if after < before {
+                    savings.push(TokenSavings {
+                        path: path.clone(),
+                        before,
+                        after,
+                    });
+                }
+            }
+            if self.rank {
+                index.add(relative, &prepared.content);
+            }
+
+            info.content = Some(prepared.content);
+            let mut fallback = None;
+            if let Some(outlined) = prepared.outline {
+                let outline_tokens = self.count(&outlined);
+                if outline_full {
+                    info.content = Some(outlined);
+                    info.mode = ContentMode::Outline;
+                } else {
+                    fallback = Some((keep_content.then_some(outlined), outline_tokens));
+                }
+            }
+            let findings = prepared.findings;
+
+            info.tokens = Some(self.count(info.content.as_deref().unwrap_or_default()));
+            if !keep_content {
+                info.content = None;
+            }
+            candidates.push((info, findings, fallback));
+        }
+
+        // Most relevant first when ranking, otherwise in scan order
+        let ranked = if self.rank {
+            index.rank(&self.instructions)
+        } else {
+            (0..candidates.len()).map(|i| (i, 0.0)).collect()
+        };
+        let mut candidates: Vec<_> = candidates.into_iter().map(Some).collect();
+
+        let mut files = Vec::new();
+        let mut included = Vec::new();
+        let mut skipped = Vec::new();
+        let mut relevance = Vec::new();
+        let mut outlined = Vec::new();
+        let mut redactions = Vec::new();
+        let mut used_tokens = 0;
+        for (position, score) in ranked {
+            let (mut info, findings, fallback) = candidates[position]
+                .take()
+                .expect("each file is ranked once");
+            let mut tokens = info.tokens.unwrap_or_default();
+
+            if self.top_k.is_some_and(|k| included.len() >= k) {
+                skipped.push(FileTokens {
+                    path: info.path,
+                    size: info.size,
+                    tokens,
+                });
+                continue;
+            }
+            // Fall back to the outline of a file that doesn't fit in full
+            let fits = |tokens| {
+                self.budget
+                    .is_none_or(|budget| used_tokens + tokens <= budget)
+            };
+            if let Some((content, outline_tokens)) = fallback.filter(|f| !fits(tokens) && fits(f.1))
+            {
+                info.content = content;
+                info.mode = ContentMode::Outline;
+                info.tokens = Some(outline_tokens);
+                tokens = outline_tokens;
+            }
+            let measured = FileTokens {
+                path: info.path.clone(),
+                size: info.size,
+                tokens,
+            };
+
+            if let Some(budget) = self.budget.filter(|budget| used_tokens + tokens > *budget) {
+                if self.fail_on_budget {
+                    return Err(PromptPalError::BudgetExceeded {
+                        path: measured.path,
+                        tokens,
+                        budget,
+                    });
+                }
+                skipped.push(measured);
+                continue;
+            }
+            if let Some(finding) = findings.first().filter(|_| self.fail_on_secrets) {
+                return Err(PromptPalError::SecretDetected {
+                    path: measured.path,
+                    kind: finding.kind.clone(),
+                    line: finding.line,
+                });
+            }
+            redactions.extend(findings.into_iter().map(|finding| Redaction {
+                path: measured.path.clone(),
+                kind: finding.kind,
+                line: finding.line,
+                matched: finding.matched,
+            }));
+            if self.rank {
+                relevance.push(Relevance {
+                    path: measured.path.clone(),
+                    score,
+                });
+            }
+            if info.mode == ContentMode::Outline {
+                outlined.push(measured.path.clone());
+            }
+            used_tokens += tokens;
+            files.push(info);
+            included.push(measured);
+        }
+
+        // Only report savings for files that made it into the prompt
+        savings.retain(|saving: &TokenSavings| included.iter().any(|f| f.path == saving.path));
+
+        Ok(Selection {
+            dependencies,
+            savings,
+            outlined,
+            files,
+            included,
+            skipped,
+            relevance,
+            sensitive,
+            redactions,
+            warnings,
+        })
+    }
+
+    /// Returns the roots the builder scans
+    pub(crate) fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Returns true if a [cache](Self::cache) was set
+    pub(crate) fn has_cache(&self) -> bool {
+        self.processor.cache.is_some()
+    }
+
+    /// Returns the filter files under the roots are scanned with
+    pub(crate) fn file_filter(&self) -> Result<FileFilter> {
+        let mut filter = FileFilter::new(&self.include, &self.exclude)?;
+        for patterns in &self.narrow {
+            filter = filter.narrow(patterns)?;
+        }
+        Ok(filter.allow_sensitive(self.allow_sensitive))
+    }
+
+    /// Counts the tokens in `text`, through the token cache if there is one
+    fn count(&self, text: &str) -> usize {
+        match &self.processor.cache {
+            Some(cache) => cache.tokens(self.encoding, text),
+            None => self.encoding.count(text),
+        }
+    }
+
+    fn not_scanned(path: &Path) -> PromptPalError {
+        PromptPalError::Io {
+            path: path.to_path_buf(),
+            source: io::Error::new(io::ErrorKind::NotFound, "not among the scanned files"),
+        }
+    }
+
+    fn report(&self, selection: Selection, prompt_tokens: usize) -> PromptReport {
+        let tokens = TokenReport::build(
+            &self.roots,
+            &selection.included,
+            context_size(self.model.as_deref()),
+            self.top_files,
+        );
+
+        PromptReport {
+            files: selection.included,
+            skipped: selection.skipped,
+            dependencies: selection.dependencies,
+            savings: selection.savings,
+            outlined: selection.outlined,
+            relevance: selection.relevance,
+            sensitive: selection.sensitive,
+            tokens,
+            prompt_tokens,
+            redactions: selection.redactions,
+            warnings: selection.warnings,
+        }
+    }
+}
+
+/// Files picked by a builder, ready to be rendered
+struct Selection {
+    dependencies: Option<ImportGraph>,
+    savings: Vec<TokenSavings>,
+    outlined: Vec<PathBuf>,
+    files: Vec<FileInfo>,
+    included: Vec<FileTokens>,
+    skipped: Vec<FileTokens>,
+    relevance: Vec<Relevance>,
+    sensitive: Vec<PathBuf>,
+    redactions: Vec<Redaction>,
+    warnings: Vec<Warning>,
+}
+
+/// Content stages applied to every file between loading and rendering
+#[derive(Debug, Clone, Default)]
+struct ContentProcessor {
+    scanner: SecretScanner,
+    /// Scan roots; rule scopes are matched against paths relative to these
+    roots: Vec<PathBuf>,
+    strip: Vec<Strip>,
+    line_numbers: bool,
+    cache: Option<Arc<Cache>>,
+}
+
+/// A file's content after [`ContentProcessor::prepare`]
+struct Prepared {
+    /// Redacted and stripped content
+    content: String,
+    /// Redacted content before stripping, if anything is stripped
+    unstripped: Option<String>,
+    /// Outline of `content`, if asked for and the language is supported
+    outline: Option<String>,
+    findings: Vec<Finding>,
+}
+
+impl ContentProcessor {
+    fn process(&self, path: &Path, content: String) -> (String, Vec<Finding>) {
+        if !self.scanner.is_active() {
+            return (content, Vec::new());
+        }
+        let relative = self.relative(path).1;
+        let Some(cache) = &self.cache else {
+            return self.scanner.redact_file(relative, &content);
+        };
+        let fingerprint = self.scanner.fingerprint(relative);
+        if cache.has_no_secrets(&fingerprint, &content) {
+            return (content, Vec::new());
+        }
+        let (redacted, findings) = self.scanner.redact_file(relative, &content);
+        if findings.is_empty() {
+            cache.set_no_secrets(&fingerprint, &content);
+        }
+        (redacted, findings)
+    }
+
+    /// Redacts, strips, outlines and numbers a file's content
+    ///
+    /// Regions of a file are always numbered, so they can be placed in the file.
+    ///
+    /// # Arguments
+    /// * `file` - The file `content` was read from; its content is ignored
+    /// * `outline` - Also build the outline of the stripped content
+    fn prepare(&self, file: &FileInfo, content: String, outline: bool) -> Prepared {
+        let path = &file.path;
+        let start_line = file.start_line;
+        let numbered = self.line_numbers || file.range.is_some();
+        let number = |content: String, sources: Option<&[Option<usize>]>| {
+            if numbered {
+                number_lines(&content, start_line, sources)
+            } else {
+                content
+            }
+        };
+        let (content, mut findings) = self.process(path, content);
+        for finding in &mut findings {
+            finding.line += start_line - 1;
+        }
+        let (content, unstripped, stripped_sources) = if self.strip.is_empty() {
+            (content, None, None)
+        } else {
+            let (stripped, sources) = strip_lines(path, &content, &self.strip);
+            (stripped, Some(content), Some(sources))
+        };
+
+        // Outline lines point into the stripped content; map them back to the file
+        let outline = outline
+            .then(|| match &self.cache {
+                Some(cache) => cache.outline(path, &content),
+                None => outline_lines(path, &content),
+            })
+            .flatten()
+            .map(|(outline, sources)| {
+                let sources: Vec<_> = sources
+                    .into_iter()
+                    .map(|line| {
+                        line.map(|line| stripped_sources.as_ref().map_or(line, |s| s[line]))
+                    })
+                    .collect();
+                number(outline, Some(&sources))
+            });
+        let sources: Option<Vec<_>> =
+            stripped_sources.map(|sources| sources.into_iter().map(Some).collect());
+        Prepared {
+            content: number(content, sources.as_deref()),
+            unstripped: unstripped.map(|content| number(content, None)),
+            outline,
+            findings,
+        }
+    }
+
+    /// Splits `path` into the root it was found under and the path relative to it
+    fn relative<'a>(&'a self, path: &'a Path) -> (&'a Path, &'a Path) {
+        self.roots
+            .iter()
+            .find_map(|root| Some((root.as_path(), path.strip_prefix(root).ok()?)))
+            .unwrap_or((Path::new(""), path))
+    }
+}
+
+impl Transform for ContentProcessor {
+    fn content(&self, file: &FileInfo, content: String) -> String {
+        let outline = file.mode == ContentMode::Outline;
+        let prepared = self.prepare(file, content, outline);
+        match prepared.outline {
+            Some(outline) => outline,
+            None => prepared.content,
+        }
+    }
+
+    fn path(&self, path: &Path) -> PathBuf {
+        let (root, relative) = self.relative(path);
+        root.join(self.scanner.redact_path(relative))
+    }
+}
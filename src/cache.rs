@@ -0,0 +1,370 @@
+use crate::error::{PromptPalError, Result};
+use crate::fileinfo::detect_file_type;
+use crate::outline::{is_supported, outline_lines};
+use crate::tokenizer::Encoding;
+use crate::walkdir::STATE_DIR_NAME;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{BufReader, BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+/// Name of the cache directory, under the state directory or the user cache directory
+pub const CACHE_DIR_NAME: &str = "cache";
+
+const CACHE_FILE_NAME: &str = "cache.json";
+
+/// Changes whenever promptpal does, since a new version may tokenize, outline or
+/// detect secrets differently
+const CACHE_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "+1");
+
+/// Texts shorter than this are cheaper to tokenize again than to look up
+const MIN_CACHED_LEN: usize = 256;
+
+/// Entries not used for this many days are dropped when the cache is saved
+const MAX_AGE_DAYS: i64 = 30;
+
+/// Token counts and other data derived from file content, keyed by a hash of the content
+///
+/// Holds token counts per encoding, outlines, and which content has no secrets for a
+/// given set of redaction rules. Since entries are keyed by what they were derived
+/// from, an edited file or a changed rule simply misses the cache and nothing needs
+/// to be invalidated by hand.
+///
+/// # Example
+/// ```rust
+/// use promptpal::{cache::Cache, Encoding};
+/// let cache = Cache::in_memory();
+/// let text = "fn main() {}\n".repeat(100);
+/// assert_eq!(
+///     cache.tokens(Encoding::Cl100kBase, &text),
+///     Encoding::Cl100kBase.count(&text)
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct Cache {
+    /// File the cache is loaded from and saved to; `None` keeps it in memory only
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    dirty: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    value: Value,
+    /// Day (since the Unix epoch) the entry was last used
+    used: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Value {
+    Tokens(usize),
+    Outline {
+        text: String,
+        sources: Vec<Option<usize>>,
+    },
+    /// The content has nothing the redaction rules would replace
+    NoSecrets,
+}
+
+/// Layout of the cache file
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: String,
+    entries: HashMap<String, Entry>,
+}
+
+impl Cache {
+    /// Creates a cache that is never saved
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Loads the cache in `dir`, starting empty if there is none yet
+    ///
+    /// A cache file from another version of promptpal, or one that can't be parsed,
+    /// is ignored and replaced on the next [`save`](Self::save).
+    pub fn open(dir: impl AsRef<Path>) -> Self {
+        let path = dir.as_ref().join(CACHE_FILE_NAME);
+        let entries = fs::File::open(&path)
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .filter(|file: &CacheFile| file.version == CACHE_VERSION)
+            .map(|file| file.entries)
+            .unwrap_or_default();
+        Cache {
+            path: Some(path),
+            state: Mutex::new(State {
+                entries,
+                dirty: false,
+            }),
+        }
+    }
+
+    /// Returns the directory the cache for `root` is kept in
+    ///
+    /// That's `.promptpal/cache` under `root` if the project already has a `.promptpal`
+    /// directory, and otherwise a directory of its own under `promptpal` in the user
+    /// cache directory (`$XDG_CACHE_HOME`, `~/.cache` on Linux).
+    pub fn location(root: &Path) -> Option<PathBuf> {
+        if root.join(STATE_DIR_NAME).is_dir() {
+            return Some(Self::project_dir(root));
+        }
+        Self::user_dir(root)
+    }
+
+    /// Directory of the cache kept in the project at `root`
+    pub fn project_dir(root: &Path) -> PathBuf {
+        root.join(STATE_DIR_NAME).join(CACHE_DIR_NAME)
+    }
+
+    /// Directory of the cache in the user cache directory for the project at `root`
+    ///
+    /// It's named by a hash of the project's canonical path, so projects never see
+    /// each other's entries and clearing one cache leaves the others alone.
+    pub fn user_dir(root: &Path) -> Option<PathBuf> {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let hash = key(&[root.as_os_str().as_encoded_bytes()]);
+        dirs::cache_dir().map(|dir| dir.join("promptpal").join(&hash[..16]))
+    }
+
+    /// Opens the cache for `root`, or an in-memory one if there's nowhere to keep it
+    pub fn discover(root: &Path) -> Self {
+        Self::location(root).map_or_else(Self::in_memory, Self::open)
+    }
+
+    /// Returns the file the cache is saved to, if it's saved at all
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Number of entries in the cache
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Counts the tokens in `text`, reusing the count from an earlier call with the same text
+    pub fn tokens(&self, encoding: Encoding, text: &str) -> usize {
+        if text.len() < MIN_CACHED_LEN {
+            return encoding.count(text);
+        }
+        let key = key(&[
+            b"tokens",
+            format!("{:?}", encoding).as_bytes(),
+            text.as_bytes(),
+        ]);
+        if let Some(Value::Tokens(count)) = self.get(&key) {
+            return count;
+        }
+        let count = encoding.count(text);
+        self.insert(key, Value::Tokens(count));
+        count
+    }
+
+    /// Like [`outline_lines`], reusing the outline of the same content in the same language
+    pub fn outline(&self, path: &Path, content: &str) -> Option<(String, Vec<Option<usize>>)> {
+        if !is_supported(path) {
+            return None;
+        }
+        let language = detect_file_type(path);
+        let key = key(&[b"outline", language.as_bytes(), content.as_bytes()]);
+        if let Some(Value::Outline { text, sources }) = self.get(&key) {
+            return Some((text, sources));
+        }
+        let (text, sources) = outline_lines(path, content)?;
+        self.insert(
+            key,
+            Value::Outline {
+                text: text.clone(),
+                sources: sources.clone(),
+            },
+        );
+        Some((text, sources))
+    }
+
+    /// Returns true if `content` was found to have no secrets under the rules described
+    /// by `rules` before
+    ///
+    /// Only the absence of secrets is remembered, so secrets never end up in the cache.
+    pub fn has_no_secrets(&self, rules: &str, content: &str) -> bool {
+        let key = key(&[b"secrets", rules.as_bytes(), content.as_bytes()]);
+        matches!(self.get(&key), Some(Value::NoSecrets))
+    }
+
+    /// Remembers that `content` has no secrets under the rules described by `rules`
+    pub fn set_no_secrets(&self, rules: &str, content: &str) {
+        let key = key(&[b"secrets", rules.as_bytes(), content.as_bytes()]);
+        self.insert(key, Value::NoSecrets);
+    }
+
+    /// Writes the cache back to disk if anything was added
+    ///
+    /// Entries not used for 30 days are dropped. The file is written to a temporary
+    /// file and renamed into place, so concurrent runs never read a partial cache.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut state = self.lock();
+        if !state.dirty {
+            return Ok(());
+        }
+        let today = today();
+        state
+            .entries
+            .retain(|_, entry| today - entry.used <= MAX_AGE_DAYS);
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir).map_err(|e| PromptPalError::from_io(dir, e))?;
+        let mut temp =
+            tempfile::NamedTempFile::new_in(dir).map_err(|e| PromptPalError::from_io(dir, e))?;
+        let file = CacheFile {
+            version: CACHE_VERSION.to_string(),
+            entries: std::mem::take(&mut state.entries),
+        };
+        let mut writer = BufWriter::new(temp.as_file_mut());
+        let written = serde_json::to_writer(&mut writer, &file)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.flush());
+        drop(writer);
+        state.entries = file.entries;
+        written.map_err(|e| PromptPalError::from_io(path, e))?;
+        temp.persist(path)
+            .map_err(|e| PromptPalError::from_io(path, e.error))?;
+        state.dirty = false;
+        Ok(())
+    }
+
+    /// Deletes the cache directory `dir`
+    ///
+    /// # Returns
+    /// * `true` if there was a cache to delete
+    pub fn clear(dir: &Path) -> Result<bool> {
+        match fs::remove_dir_all(dir) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(PromptPalError::from_io(dir, e)),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        let mut state = self.lock();
+        let today = today();
+        let entry = state.entries.get_mut(key)?;
+        let value = entry.value.clone();
+        if entry.used != today {
+            entry.used = today;
+            state.dirty = true;
+        }
+        Some(value)
+    }
+
+    fn insert(&self, key: String, value: Value) {
+        let mut state = self.lock();
+        state.entries.insert(
+            key,
+            Entry {
+                value,
+                used: today(),
+            },
+        );
+        state.dirty = true;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A panic while holding the lock can't leave a half-written entry behind
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Hashes the parts of a key, each prefixed with its length so they can't run together
+fn key(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+fn today() -> i64 {
+    chrono::Utc::now().timestamp() / 86_400
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cache_round_trip() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let text = "fn main() { println!(\"hello\"); }\n".repeat(20);
+        let count = Encoding::Cl100kBase.count(&text);
+
+        let cache = Cache::open(dir.path());
+        assert_eq!(cache.tokens(Encoding::Cl100kBase, &text), count);
+        // Counts are kept per encoding, and short texts aren't remembered
+        cache.tokens(Encoding::O200kBase, &text);
+        cache.tokens(Encoding::Cl100kBase, "short");
+        assert!(cache.outline(Path::new("main.rs"), &text).is_some());
+        assert!(cache.outline(Path::new("notes.txt"), &text).is_none());
+        cache.set_no_secrets("builtin", &text);
+        assert_eq!(cache.len(), 4);
+        cache.save()?;
+
+        let reopened = Cache::open(dir.path());
+        assert_eq!(reopened.len(), 4);
+        assert_eq!(reopened.tokens(Encoding::Cl100kBase, &text), count);
+        assert!(reopened.has_no_secrets("builtin", &text));
+        assert!(!reopened.has_no_secrets("builtin+custom", &text));
+        assert!(!reopened.has_no_secrets("builtin", &text.replace("hello", "bye")));
+        assert_eq!(
+            reopened.outline(Path::new("lib.rs"), &text),
+            outline_lines(Path::new("lib.rs"), &text)
+        );
+
+        // A cache from another version is ignored
+        let file = dir.path().join(CACHE_FILE_NAME);
+        let stale = fs::read_to_string(&file)
+            .unwrap()
+            .replace(CACHE_VERSION, "0.0.0");
+        fs::write(&file, stale).unwrap();
+        assert!(Cache::open(dir.path()).is_empty());
+
+        // Projects outside of the user cache directory each get their own
+        let other = tempdir().unwrap();
+        if let Some(user_dir) = Cache::user_dir(dir.path()) {
+            assert_eq!(
+                Cache::user_dir(&dir.path().join(".")),
+                Some(user_dir.clone())
+            );
+            assert_ne!(Cache::user_dir(other.path()), Some(user_dir));
+        }
+
+        assert!(Cache::clear(dir.path())?);
+        assert!(!Cache::clear(dir.path())?);
+        Ok(())
+    }
+}
@@ -1,34 +1,437 @@
-use clap::Parser;
-use std::path::PathBuf;
+use crate::ask::DEFAULT_API_KEY_ENV;
+use crate::prompts::parse_var;
+use crate::render::OutputFormat;
+use crate::report::ReportFormat;
+use crate::spec::FileSpec;
+use crate::strip::Strip;
+use crate::tokenizer::Encoding;
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
+/// Without a subcommand, the top-level flags work as before: `-x` renders the prompt
+/// like `pack`, and otherwise tokens are counted like `count`.
 #[derive(Parser, Debug)]
 #[command(
     author,
     version,
-    about = "A tool for analyzing and processing prompt files"
+    about = "A tool for analyzing and processing prompt files",
+    subcommand_negates_reqs = true
 )]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Generate prompt output, like the pack subcommand (XML unless --format says otherwise)
+    #[arg(short, long)]
+    pub xml: bool,
+
+    #[command(flatten)]
+    pub pack: PackArgs,
+
+    /// Turn debugging information on
+    #[arg(short = 'v', long, global = true, action = clap::ArgAction::Count)]
+    pub debug: u8,
+}
+
+impl Cli {
+    /// Returns the subcommand to run, mapping the top-level flags to `pack` or `count`
+    pub fn into_command(self) -> Command {
+        match self.command {
+            Some(command) => command,
+            None if self.xml => Command::Pack(Box::new(self.pack)),
+            None => Command::Count(CountArgs {
+                scan: self.pack.scan,
+                per_file: self.pack.per_file,
+            }),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render the prompt: tree view, instructions and file contents
+    Pack(Box<PackArgs>),
+    /// Count the tokens in each file
+    Count(CountArgs),
+    /// Print only the tree view of the files that would be included
+    Tree(TreeArgs),
+    /// Pick files in an interactive tree, then render the prompt from them
+    Pick(Box<PickArgs>),
+    /// Render the prompt and send it to an OpenAI-compatible chat endpoint
+    Ask(Box<AskArgs>),
+    /// Break down token usage by directory, file type and heaviest files
+    Report(ReportArgs),
+    /// Write the files in an LLM response back under a directory
+    Unpack(UnpackArgs),
+    /// Apply the unified diffs or SEARCH/REPLACE blocks in an LLM response
+    Apply(ApplyArgs),
+    /// Manage the cache of token counts, outlines and secret scans
+    Cache(CacheArgs),
+    /// Serve an HTTP/JSON API for scanning, counting and rendering a directory
+    Serve(ServeArgs),
+    /// Serve a directory to agents as a Model Context Protocol server over stdio
+    Mcp(ServiceArgs),
+}
+
+/// Which files to scan, shared by the pack, count, tree and report subcommands
+#[derive(Args, Debug)]
+pub struct ScanArgs {
     /// Directory to scan for prompt files
-    #[arg(short, long, value_name = "DIR")]
-    pub directory: PathBuf,
+    #[arg(short, long, value_name = "DIR", required = true)]
+    pub directory: Option<PathBuf>,
+
+    /// Apply this profile from the configuration files
+    #[arg(short, long, value_name = "NAME")]
+    pub profile: Option<String>,
 
-    /// Instructions for the prompt
+    /// Ignore .promptpal.toml and the user configuration file
+    #[arg(long, conflicts_with = "profile")]
+    pub no_config: bool,
+
+    /// Only include files matching this glob, relative to the directory (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Leave out files and directories matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Include files that usually hold credentials (.env*, private keys, credentials.json, ...)
+    #[arg(long, overrides_with = "no_allow_sensitive")]
+    pub allow_sensitive: bool,
+
+    /// Skip files that usually hold credentials, even if the configuration allows them
+    #[arg(long, overrides_with = "allow_sensitive")]
+    pub no_allow_sensitive: bool,
+
+    /// Encoding used to count tokens
+    #[arg(long, value_enum, default_value_t = Encoding::Cl100kBase)]
+    pub encoding: Encoding,
+
+    /// Tokenize every file again instead of reading and updating the cache
+    #[arg(long, overrides_with = "cache")]
+    pub no_cache: bool,
+
+    /// Use the cache even if the configuration turns it off
+    #[arg(long, overrides_with = "no_cache")]
+    pub cache: bool,
+}
+
+impl ScanArgs {
+    /// Returns the directory to scan, which clap requires to be given
+    pub fn directory(&self) -> &Path {
+        self.directory
+            .as_deref()
+            .expect("--directory is a required argument")
+    }
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("instruction_source").args(["instructions", "instructions_file", "task"])))]
+pub struct PackArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// Instructions for the prompt ("-" reads them from stdin)
     #[arg(short = 'n', long, value_name = "INSTRUCTIONS")]
     pub instructions: Option<String>,
 
-    /// Generate XML output
-    #[arg(short, long)]
-    pub xml: bool,
+    /// Read the instructions from a file ("-" for stdin)
+    #[arg(long, value_name = "FILE")]
+    pub instructions_file: Option<PathBuf>,
+
+    /// Use the named instructions template from the prompt library
+    #[arg(short, long, value_name = "NAME")]
+    pub task: Option<String>,
+
+    /// Set a variable used by the --task template (repeatable)
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var, requires = "task")]
+    pub vars: Vec<(String, String)>,
+
+    /// Format of the generated prompt
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Xml)]
+    pub format: OutputFormat,
+
+    /// Maximum tokens of file content to include; files that don't fit are skipped
+    #[arg(short, long, value_name = "TOKENS")]
+    pub budget: Option<usize>,
+
+    /// Fail instead of skipping files when they don't fit in the budget
+    #[arg(long, requires = "budget")]
+    pub fail_on_budget: bool,
+
+    /// Strip content to save tokens: comments, blank lines and/or license headers
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "WHAT")]
+    pub strip: Vec<Strip>,
+
+    /// Render files matching this glob as outlines: signatures and doc comments only (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub outline: Vec<String>,
+
+    /// Include the outline of files that don't fit in the budget, when the outline does
+    #[arg(long, requires = "budget")]
+    pub outline_fallback: bool,
+
+    /// Prefix each content line with its line number in the file
+    #[arg(short = 'l', long, overrides_with = "no_line_numbers")]
+    pub line_numbers: bool,
+
+    /// Leave out line numbers, even if the configuration turns them on
+    #[arg(long, overrides_with = "line_numbers")]
+    pub no_line_numbers: bool,
+
+    /// Pick files by relevance to the instructions, so the budget keeps the most relevant ones
+    #[arg(long, requires = "instruction_source")]
+    pub rank: bool,
+
+    /// Only include the N files most relevant to the instructions (implies --rank)
+    #[arg(long, value_name = "N", requires = "instruction_source")]
+    pub top_k: Option<usize>,
+
+    /// Only include this file, directory or region: path, path:120-340 or path:fn name (repeatable)
+    #[arg(long = "file", value_name = "SPEC")]
+    pub files: Vec<FileSpec>,
+
+    /// Read --file specs from a file, one per line (# starts a comment)
+    #[arg(long, value_name = "FILE")]
+    pub files_from: Option<PathBuf>,
+
+    /// Only include this file and what it imports, relative to the directory (repeatable)
+    #[arg(long, value_name = "FILE")]
+    pub follow_imports: Vec<PathBuf>,
+
+    /// How many import hops to follow from --follow-imports (default: all)
+    #[arg(long, value_name = "N", requires = "follow_imports")]
+    pub depth: Option<usize>,
+
+    /// Emit the import graph as a <dependencies> section
+    #[arg(long, requires = "follow_imports")]
+    pub dependencies: bool,
+
+    /// Include secrets as-is instead of replacing them with [REDACTED:kind] placeholders
+    #[arg(long, overrides_with = "redact")]
+    pub no_redact: bool,
+
+    /// Redact secrets, even if the configuration turns redaction off
+    #[arg(long, overrides_with = "no_redact")]
+    pub redact: bool,
+
+    /// Fail if any secret is found instead of redacting it
+    #[arg(
+        long,
+        conflicts_with = "no_redact",
+        overrides_with = "no_fail_on_secrets"
+    )]
+    pub fail_on_secrets: bool,
+
+    /// Redact secrets instead of failing, even if the configuration says to fail
+    #[arg(long, overrides_with = "fail_on_secrets")]
+    pub no_fail_on_secrets: bool,
+
+    /// Custom redaction rules file (defaults to redactions.toml in the directory, if present)
+    #[arg(long, value_name = "FILE")]
+    pub redactions: Option<PathBuf>,
+
+    /// Print what the redaction rules would replace, with line numbers, and write no prompt
+    #[arg(long)]
+    pub redact_dry_run: bool,
+
+    /// Model whose context window token counts are measured against
+    #[arg(short, long, value_name = "MODEL")]
+    pub model: Option<String>,
+
+    /// Annotate the XML tree view with token counts and sizes
+    #[arg(short = 'a', long = "annotate-tree")]
+    pub annotate_tree: bool,
+
+    /// Output file for results (optional)
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
 
     /// Show per-file token counts (default only shows total)
     #[arg(short = 'i', long = "per-file")]
     pub per_file: bool,
 
-    /// Output file for results (optional)
+    /// Keep running and rewrite the output whenever a scanned file changes
+    #[arg(short, long, requires = "output", conflicts_with = "redact_dry_run")]
+    pub watch: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct PickArgs {
+    #[command(flatten)]
+    pub pack: PackArgs,
+
+    /// Save the selection as a file list for --files-from
+    #[arg(long, value_name = "FILE")]
+    pub save: Option<PathBuf>,
+}
+
+/// Pack options, with `--model` naming the model to ask and `--output` saving its answer
+#[derive(Args, Debug)]
+pub struct AskArgs {
+    #[command(flatten)]
+    pub pack: PackArgs,
+
+    /// Base URL of the OpenAI-compatible API, up to and including /v1
+    #[arg(long, value_name = "URL", default_value = "http://localhost:8080/v1")]
+    pub endpoint: String,
+
+    /// Environment variable holding the API key, sent as a bearer token when set
+    #[arg(long, value_name = "VAR", default_value = DEFAULT_API_KEY_ENV)]
+    pub api_key_env: String,
+
+    /// System message sent before the prompt
+    #[arg(long, value_name = "MESSAGE")]
+    pub system: Option<String>,
+
+    /// Sampling temperature
+    #[arg(long, value_name = "T")]
+    pub temperature: Option<f32>,
+
+    /// Wait for the whole answer instead of streaming it
+    #[arg(long)]
+    pub no_stream: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CountArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// Show per-file token counts (default only shows total)
+    #[arg(short = 'i', long = "per-file")]
+    pub per_file: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TreeArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// Annotate the tree view with token counts and sizes
+    #[arg(short = 'a', long = "annotate-tree")]
+    pub annotate_tree: bool,
+
+    /// Output file for the tree view (optional)
     #[arg(short, long, value_name = "FILE")]
     pub output: Option<PathBuf>,
+}
 
-    /// Turn debugging information on
-    #[arg(short = 'v', long, action = clap::ArgAction::Count)]
-    pub debug: u8,
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// Model whose context window the percentages are measured against
+    #[arg(short, long, value_name = "MODEL")]
+    pub model: Option<String>,
+
+    /// Number of heaviest files to highlight
+    #[arg(short = 'k', long, value_name = "N", default_value_t = 10)]
+    pub top: usize,
+
+    /// Report format
+    #[arg(short, long, value_enum, default_value_t = ReportFormat::Table)]
+    pub format: ReportFormat,
+
+    /// Output file for the report (optional)
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct UnpackArgs {
+    /// LLM response holding <file> elements with <path> and <content> ("-" for stdin)
+    #[arg(value_name = "RESPONSE")]
+    pub input: PathBuf,
+
+    /// Directory the files are written under
+    #[arg(short, long, value_name = "DIR", default_value = ".")]
+    pub directory: PathBuf,
+
+    /// Show what would change without writing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Overwrite files without backing them up to .promptpal/backups
+    #[arg(long)]
+    pub no_backup: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ApplyArgs {
+    /// LLM response holding unified diffs or SEARCH/REPLACE blocks ("-" for stdin)
+    #[arg(value_name = "RESPONSE", default_value = "-")]
+    pub input: PathBuf,
+
+    /// Directory the patched files are under
+    #[arg(short, long, value_name = "DIR", default_value = ".")]
+    pub directory: PathBuf,
+
+    /// Show the resulting changes without writing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Change files without backing them up to .promptpal/backups
+    #[arg(long)]
+    pub no_backup: bool,
+}
+
+/// Settings of a long-running service; requests can narrow them but not loosen them
+#[derive(Args, Debug)]
+pub struct ServiceArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// Model whose context window token counts are measured against
+    #[arg(short, long, value_name = "MODEL")]
+    pub model: Option<String>,
+
+    /// Largest token budget a request may use
+    #[arg(short, long, value_name = "TOKENS")]
+    pub budget: Option<usize>,
+
+    /// Include secrets as-is instead of replacing them with [REDACTED:kind] placeholders
+    #[arg(long, overrides_with = "redact")]
+    pub no_redact: bool,
+
+    /// Redact secrets, even if the configuration turns redaction off
+    #[arg(long, overrides_with = "no_redact")]
+    pub redact: bool,
+
+    /// Custom redaction rules file (defaults to redactions.toml in the directory, if present)
+    #[arg(long, value_name = "FILE")]
+    pub redactions: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    #[command(flatten)]
+    pub service: ServiceArgs,
+
+    /// Port to listen on
+    #[arg(long, default_value_t = 7878)]
+    pub port: u16,
+
+    /// Address to listen on; anything but localhost exposes the directory to the network
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1")]
+    pub host: IpAddr,
+}
+
+#[derive(Args, Debug)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub command: CacheCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// Delete a project's cache, in its .promptpal/cache and in the user cache directory
+    Clear {
+        /// Project directory whose caches are deleted
+        #[arg(short, long, value_name = "DIR", default_value = ".")]
+        directory: PathBuf,
+    },
 }
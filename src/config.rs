@@ -0,0 +1,371 @@
+use crate::error::{PromptPalError, Result};
+use crate::render::OutputFormat;
+use crate::strip::Strip;
+use crate::tokenizer::Encoding;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the project configuration file, looked up from the scan root upwards
+pub const CONFIG_FILE_NAME: &str = ".promptpal.toml";
+
+/// Settings that can be given in a configuration file or one of its profiles
+///
+/// Every field is optional; unset fields fall back to the layer below (profile,
+/// then project config, then user config, then built-in defaults).
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub format: Option<OutputFormat>,
+    pub model: Option<String>,
+    pub encoding: Option<Encoding>,
+    pub budget: Option<usize>,
+    pub strip: Vec<Strip>,
+    pub outline: Vec<String>,
+    pub line_numbers: Option<bool>,
+    pub allow_sensitive: Option<bool>,
+    /// Cache token counts, outlines and secret scans (on by default)
+    pub cache: Option<bool>,
+    /// Redact secrets (on by default)
+    pub redact: Option<bool>,
+    pub fail_on_secrets: Option<bool>,
+    /// Custom redaction rules file, relative to the configuration file
+    pub redactions: Option<PathBuf>,
+    /// Default instructions for the prompt
+    pub instructions: Option<String>,
+    /// File holding the default instructions, relative to the configuration file
+    pub template: Option<PathBuf>,
+    /// Prompt library directory searched first for `--task`, relative to the configuration file
+    pub prompts: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Returns these settings with every field set in `over` replaced by its value
+    ///
+    /// Lists are replaced as a whole rather than appended to.
+    pub fn merge(self, over: Settings) -> Settings {
+        fn list<T>(base: Vec<T>, over: Vec<T>) -> Vec<T> {
+            if over.is_empty() {
+                base
+            } else {
+                over
+            }
+        }
+        Settings {
+            include: list(self.include, over.include),
+            exclude: list(self.exclude, over.exclude),
+            format: over.format.or(self.format),
+            model: over.model.or(self.model),
+            encoding: over.encoding.or(self.encoding),
+            budget: over.budget.or(self.budget),
+            strip: list(self.strip, over.strip),
+            outline: list(self.outline, over.outline),
+            line_numbers: over.line_numbers.or(self.line_numbers),
+            allow_sensitive: over.allow_sensitive.or(self.allow_sensitive),
+            cache: over.cache.or(self.cache),
+            redact: over.redact.or(self.redact),
+            fail_on_secrets: over.fail_on_secrets.or(self.fail_on_secrets),
+            redactions: over.redactions.or(self.redactions),
+            instructions: over.instructions.or(self.instructions),
+            template: over.template.or(self.template),
+            prompts: over.prompts.or(self.prompts),
+        }
+    }
+
+    /// Reads the instructions from `instructions`, or else from the `template` file
+    pub fn instructions(&self) -> Result<Option<String>> {
+        if let Some(instructions) = &self.instructions {
+            return Ok(Some(instructions.clone()));
+        }
+        let Some(path) = &self.template else {
+            return Ok(None);
+        };
+        fs::read_to_string(path)
+            .map(|text| Some(text.trim_end().to_string()))
+            .map_err(|e| PromptPalError::Template {
+                path: path.clone(),
+                message: e.to_string(),
+            })
+    }
+
+    /// Makes file paths relative to `dir`, the directory of the file they were read from
+    ///
+    /// With `confined`, a path that leads outside `dir` is refused, so a project's
+    /// configuration can't pull in files from elsewhere on the machine.
+    fn resolve_paths(&mut self, dir: &Path, confined: bool) -> std::result::Result<(), String> {
+        for (key, path) in [
+            ("redactions", &mut self.redactions),
+            ("template", &mut self.template),
+            ("prompts", &mut self.prompts),
+        ] {
+            let Some(path) = path else { continue };
+            if confined && escapes(dir, path) {
+                return Err(format!(
+                    "{} = \"{}\" points outside {}",
+                    key,
+                    path.display(),
+                    dir.display()
+                ));
+            }
+            *path = dir.join(&*path);
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path`, relative to `dir`, leads outside of it
+///
+/// `..` and absolute paths are checked as written; an existing path is also
+/// resolved, so a symlink can't lead elsewhere either.
+fn escapes(dir: &Path, path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+    match (dir.join(path).canonicalize(), dir.canonicalize()) {
+        (Ok(target), Ok(dir)) => !target.starts_with(dir),
+        _ => false,
+    }
+}
+
+/// Layout of a configuration file: top-level settings plus `[profiles.<name>]` tables
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(flatten)]
+    settings: Settings,
+    profiles: BTreeMap<String, Settings>,
+}
+
+/// Layered configuration from the user and project configuration files
+///
+/// ```toml
+/// # .promptpal.toml
+/// exclude = ["target/**", "*.lock"]
+/// format = "markdown"
+/// budget = 60000
+///
+/// [profiles.review]
+/// template = "prompts/review.md"
+/// strip = ["comments"]
+///
+/// [profiles.architecture]
+/// outline = ["**/*.rs"]
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    settings: Settings,
+    profiles: BTreeMap<String, Settings>,
+    /// Files the configuration was read from, lowest precedence first
+    sources: Vec<PathBuf>,
+}
+
+impl Config {
+    /// Loads the user configuration, then the project configuration found from `root` upwards
+    pub fn discover(root: &Path) -> Result<Self> {
+        let mut config = Config::default();
+        if let Some(path) = user_config_path().filter(|path| path.is_file()) {
+            config.layer(&path, false)?;
+        }
+        if let Some(path) = find_project_config(root) {
+            config.layer(&path, true)?;
+        }
+        Ok(config)
+    }
+
+    /// Loads a single project configuration file
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut config = Config::default();
+        config.layer(path, true)?;
+        Ok(config)
+    }
+
+    /// Reads `path` on top of what has been loaded so far
+    ///
+    /// Only the user's own configuration (`confined` false) may point at files outside
+    /// its directory.
+    fn layer(&mut self, path: &Path, confined: bool) -> Result<()> {
+        let text = fs::read_to_string(path).map_err(|e| PromptPalError::from_io(path, e))?;
+        let mut file: ConfigFile = toml::from_str(&text).map_err(|e| PromptPalError::Config {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+
+        let invalid = |message| PromptPalError::Config {
+            path: path.to_path_buf(),
+            message,
+        };
+        let dir = path.parent().unwrap_or(Path::new(""));
+        file.settings
+            .resolve_paths(dir, confined)
+            .map_err(invalid)?;
+        self.settings = std::mem::take(&mut self.settings).merge(file.settings);
+        for (name, mut profile) in file.profiles {
+            profile.resolve_paths(dir, confined).map_err(invalid)?;
+            let base = self.profiles.remove(&name).unwrap_or_default();
+            self.profiles.insert(name, base.merge(profile));
+        }
+        self.sources.push(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Files the configuration was read from, lowest precedence first
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// Names of the profiles defined across all configuration files
+    pub fn profile_names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    /// Returns the settings to use, with the named profile applied on top if given
+    pub fn settings(&self, profile: Option<&str>) -> Result<Settings> {
+        let Some(name) = profile else {
+            return Ok(self.settings.clone());
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(self.settings.clone().merge(profile.clone())),
+            None => Err(PromptPalError::Config {
+                path: self
+                    .sources
+                    .last()
+                    .cloned()
+                    .unwrap_or_else(|| PathBuf::from(CONFIG_FILE_NAME)),
+                message: if self.profiles.is_empty() {
+                    format!("unknown profile '{}' (no profiles are defined)", name)
+                } else {
+                    let names: Vec<_> = self.profile_names().collect();
+                    format!(
+                        "unknown profile '{}' (available: {})",
+                        name,
+                        names.join(", ")
+                    )
+                },
+            }),
+        }
+    }
+}
+
+/// Path of the user-level configuration file, e.g. `~/.config/promptpal/config.toml`
+pub fn user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("promptpal").join("config.toml"))
+}
+
+/// Finds the nearest `.promptpal.toml` in `root` or one of its ancestors
+pub fn find_project_config(root: &Path) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    root.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_layers_and_profiles() {
+        let dir = tempdir().unwrap();
+        let user = dir.path().join("user.toml");
+        fs::write(
+            &user,
+            "format = \"markdown\"\nbudget = 1000\n\n[profiles.review]\nmodel = \"gpt-4o\"\n",
+        )
+        .unwrap();
+        let project = dir.path().join("project").join(CONFIG_FILE_NAME);
+        fs::create_dir_all(project.parent().unwrap()).unwrap();
+        fs::write(
+            &project,
+            "budget = 5000\nexclude = [\"target/**\"]\n\n[profiles.review]\ntemplate = \"review.md\"\nstrip = [\"comments\", \"blank\"]\n",
+        )
+        .unwrap();
+
+        let nested = project.parent().unwrap().join("src").join("deep");
+        fs::create_dir_all(&nested).unwrap();
+        let found = find_project_config(&nested).unwrap();
+        assert_eq!(found, project.canonicalize().unwrap());
+
+        let mut config = Config::load(&user).unwrap();
+        config.layer(&found, true).unwrap();
+        let base = config.settings(None).unwrap();
+        assert_eq!(base.format, Some(OutputFormat::Markdown));
+        assert_eq!(base.budget, Some(5000));
+        assert_eq!(base.exclude, vec!["target/**"]);
+        assert_eq!(base.model, None);
+
+        let review = config.settings(Some("review")).unwrap();
+        assert_eq!(review.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(review.strip, vec![Strip::Comments, Strip::Blank]);
+        assert_eq!(
+            review.template,
+            Some(found.parent().unwrap().join("review.md"))
+        );
+
+        let err = config.settings(Some("missing")).unwrap_err();
+        assert!(err.to_string().contains("available: review"));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        fs::write(&path, "budgte = 10\n").unwrap();
+        assert!(matches!(
+            Config::load(&path),
+            Err(PromptPalError::Config { .. })
+        ));
+        fs::write(&path, "format = \"html\"\n").unwrap();
+        assert!(Config::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_project_paths_stay_in_its_directory() {
+        let dir = tempdir().unwrap();
+        let project = dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+        let path = project.join(CONFIG_FILE_NAME);
+
+        fs::write(&path, "template = \"prompts/../review.md\"\n").unwrap();
+        let settings = Config::load(&path).unwrap().settings(None).unwrap();
+        assert_eq!(
+            settings.template,
+            Some(project.join("prompts/../review.md"))
+        );
+
+        for setting in [
+            "template = \"../secret.txt\"",
+            "redactions = \"/etc/passwd\"",
+            "[profiles.review]\nprompts = \"prompts/../../elsewhere\"",
+        ] {
+            fs::write(&path, setting).unwrap();
+            let err = Config::load(&path).unwrap_err();
+            assert!(err.to_string().contains("points outside"), "{}", err);
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path(), project.join("link")).unwrap();
+            fs::write(&path, "prompts = \"link\"\n").unwrap();
+            assert!(Config::load(&path).is_err());
+        }
+
+        // The user's own configuration may point anywhere
+        let mut config = Config::default();
+        config.layer(&path, false).unwrap();
+        fs::write(&path, "template = \"../secret.txt\"\n").unwrap();
+        config.layer(&path, false).unwrap();
+        assert_eq!(
+            config.settings(None).unwrap().template,
+            Some(project.join("../secret.txt"))
+        );
+    }
+}
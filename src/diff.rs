@@ -0,0 +1,194 @@
+use std::fmt::Write;
+
+/// Lines of context shown around each change in a unified diff
+const CONTEXT: usize = 3;
+
+/// One step of a line diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    /// Line `old` of the old text equals line `new` of the new text
+    Equal { old: usize, new: usize },
+    /// Line `old` of the old text was removed
+    Delete { old: usize },
+    /// Line `new` of the new text was added
+    Insert { new: usize },
+}
+
+/// Computes a shortest line diff between `old` and `new` with Myers' algorithm
+pub fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    // Furthest reaching x on each diagonal before each round, for backtracking
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k =
+            if k == -d || (k != d && v[(k - 1 + offset) as usize] < v[(k + 1 + offset) as usize]) {
+                k + 1
+            } else {
+                k - 1
+            };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal {
+                old: x as usize,
+                new: y as usize,
+            });
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert {
+                    new: prev_y as usize,
+                });
+            } else {
+                edits.push(Edit::Delete {
+                    old: prev_x as usize,
+                });
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+/// Renders the changes from `old` to `new` as a unified diff
+///
+/// Returns an empty string when the texts have the same lines.
+///
+/// # Arguments
+/// * `old_name` - Label for the old text, e.g. `a/src/lib.rs` or `/dev/null`
+/// * `new_name` - Label for the new text
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old_lines: Vec<_> = old.lines().collect();
+    let new_lines: Vec<_> = new.lines().collect();
+    let edits = diff_lines(&old_lines, &new_lines);
+    let changed: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal { .. }))
+        .map(|(i, _)| i)
+        .collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // Group changes whose context overlaps into hunks of edit indices
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (start, end) in hunks {
+        let hunk = &edits[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Insert { .. }))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Delete { .. }))
+            .count();
+        // Line numbers where the hunk starts on each side, 1-based (0 for an empty side)
+        let (old_start, new_start) =
+            edits[..start]
+                .iter()
+                .fold((0, 0), |(o, n), edit| match edit {
+                    Edit::Equal { .. } => (o + 1, n + 1),
+                    Edit::Delete { .. } => (o + 1, n),
+                    Edit::Insert { .. } => (o, n + 1),
+                });
+        let _ = writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            old_start + usize::from(old_count > 0),
+            old_count,
+            new_start + usize::from(new_count > 0),
+            new_count
+        );
+        for edit in hunk {
+            let _ = match *edit {
+                Edit::Equal { old, .. } => writeln!(out, " {}", old_lines[old]),
+                Edit::Delete { old } => writeln!(out, "-{}", old_lines[old]),
+                Edit::Insert { new } => writeln!(out, "+{}", new_lines[new]),
+            };
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        let old = ["a", "b", "c", "d"];
+        let new = ["a", "c", "d", "e"];
+        let edits = diff_lines(&old, &new);
+        assert_eq!(
+            edits,
+            vec![
+                Edit::Equal { old: 0, new: 0 },
+                Edit::Delete { old: 1 },
+                Edit::Equal { old: 2, new: 1 },
+                Edit::Equal { old: 3, new: 2 },
+                Edit::Insert { new: 3 },
+            ]
+        );
+        assert!(diff_lines(&[], &[]).is_empty());
+        assert_eq!(diff_lines(&[], &["x"]), vec![Edit::Insert { new: 0 }]);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = (1..=10).map(|i| format!("{}\n", i)).collect::<String>();
+        let new = old.replace("2\n", "two\n").replace("9\n", "");
+        assert_eq!(
+            unified_diff(&old, &new, "a/n.txt", "b/n.txt"),
+            "--- a/n.txt\n+++ b/n.txt\n@@ -1,10 +1,9 @@\n 1\n-2\n+two\n 3\n 4\n 5\n 6\n 7\n 8\n-9\n 10\n"
+        );
+        assert_eq!(
+            unified_diff("", "new\n", "/dev/null", "b/new.txt"),
+            "--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,1 @@\n+new\n"
+        );
+        assert_eq!(unified_diff("same\n", "same\n", "a", "b"), "");
+    }
+}
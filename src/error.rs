@@ -0,0 +1,243 @@
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Result type used throughout the library
+pub type Result<T> = std::result::Result<T, PromptPalError>;
+
+/// Errors that can occur while scanning, reading and rendering files
+#[derive(Debug)]
+pub enum PromptPalError {
+    /// A directory to scan does not exist
+    MissingRoot { path: PathBuf },
+    /// A directory to scan is not a directory
+    NotADirectory { path: PathBuf },
+    /// A file or directory could not be read due to permissions
+    PermissionDenied { path: PathBuf, source: io::Error },
+    /// A file's content could not be decoded
+    DecodeFailure { path: PathBuf, message: String },
+    /// A file did not fit in the token budget
+    BudgetExceeded {
+        path: PathBuf,
+        tokens: usize,
+        budget: usize,
+    },
+    /// A secret was found while redaction was set to fail
+    SecretDetected {
+        path: PathBuf,
+        kind: String,
+        line: usize,
+    },
+    /// A configuration or rules file is invalid
+    Config { path: PathBuf, message: String },
+    /// A template could not be loaded or rendered
+    Template { path: PathBuf, message: String },
+    /// A git operation failed
+    Git { path: PathBuf, message: String },
+    /// The lines or symbol selected from a file could not be found
+    Region { path: PathBuf, message: String },
+    /// A file to write resolves to a path outside the target directory
+    OutsideRoot { path: PathBuf },
+    /// A file to write is inside `.git` or `.promptpal`
+    Protected { path: PathBuf },
+    /// A directory could not be watched for changes
+    Watch { path: PathBuf, message: String },
+    /// A chat completions endpoint could not be reached or returned an error
+    Endpoint { url: String, message: String },
+    /// A glob pattern could not be parsed
+    InvalidPattern { pattern: String, message: String },
+    /// Rendered output could not be written
+    Output { source: io::Error },
+    /// Any other I/O error
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl PromptPalError {
+    /// Classifies an I/O error that occurred while accessing `path`
+    pub fn from_io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        let path = path.into();
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => PromptPalError::PermissionDenied { path, source },
+            io::ErrorKind::InvalidData => PromptPalError::DecodeFailure {
+                path,
+                message: source.to_string(),
+            },
+            _ => PromptPalError::Io { path, source },
+        }
+    }
+
+    /// Returns the path the error relates to, if any
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            PromptPalError::MissingRoot { path }
+            | PromptPalError::NotADirectory { path }
+            | PromptPalError::PermissionDenied { path, .. }
+            | PromptPalError::DecodeFailure { path, .. }
+            | PromptPalError::BudgetExceeded { path, .. }
+            | PromptPalError::SecretDetected { path, .. }
+            | PromptPalError::Config { path, .. }
+            | PromptPalError::Template { path, .. }
+            | PromptPalError::Git { path, .. }
+            | PromptPalError::Region { path, .. }
+            | PromptPalError::OutsideRoot { path }
+            | PromptPalError::Protected { path }
+            | PromptPalError::Watch { path, .. }
+            | PromptPalError::Io { path, .. } => Some(path),
+            PromptPalError::Endpoint { .. }
+            | PromptPalError::InvalidPattern { .. }
+            | PromptPalError::Output { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for PromptPalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PromptPalError::MissingRoot { path } => {
+                write!(f, "Directory not found: {}", path.display())
+            }
+            PromptPalError::NotADirectory { path } => {
+                write!(f, "Path is not a directory: {}", path.display())
+            }
+            PromptPalError::PermissionDenied { path, .. } => {
+                write!(f, "Permission denied: {}", path.display())
+            }
+            PromptPalError::DecodeFailure { path, message } => {
+                write!(f, "Failed to decode {}: {}", path.display(), message)
+            }
+            PromptPalError::BudgetExceeded {
+                path,
+                tokens,
+                budget,
+            } => write!(
+                f,
+                "{} ({} tokens) does not fit in the {}-token budget",
+                path.display(),
+                tokens,
+                budget
+            ),
+            PromptPalError::SecretDetected { path, kind, line } => {
+                write!(f, "Secret ({}) found in {}:{}", kind, path.display(), line)
+            }
+            PromptPalError::Config { path, message } => {
+                write!(
+                    f,
+                    "Invalid configuration in {}: {}",
+                    path.display(),
+                    message
+                )
+            }
+            PromptPalError::Template { path, message } => {
+                write!(f, "Template error in {}: {}", path.display(), message)
+            }
+            PromptPalError::Git { path, message } => {
+                write!(f, "Git error in {}: {}", path.display(), message)
+            }
+            PromptPalError::Region { path, message } => {
+                write!(f, "Cannot select from {}: {}", path.display(), message)
+            }
+            PromptPalError::OutsideRoot { path } => write!(
+                f,
+                "Refusing to write outside the target directory: {}",
+                path.display()
+            ),
+            PromptPalError::Protected { path } => write!(
+                f,
+                "Refusing to write into .git or .promptpal: {}",
+                path.display()
+            ),
+            PromptPalError::Watch { path, message } => {
+                write!(f, "Failed to watch {}: {}", path.display(), message)
+            }
+            PromptPalError::Endpoint { url, message } => {
+                write!(f, "Request to {} failed: {}", url, message)
+            }
+            PromptPalError::InvalidPattern { pattern, message } => {
+                write!(f, "Invalid pattern '{}': {}", pattern, message)
+            }
+            PromptPalError::Output { source } => write!(f, "Failed to write output: {}", source),
+            PromptPalError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for PromptPalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PromptPalError::PermissionDenied { source, .. }
+            | PromptPalError::Output { source }
+            | PromptPalError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<PromptPalError> for io::Error {
+    fn from(err: PromptPalError) -> Self {
+        let kind = match &err {
+            PromptPalError::MissingRoot { .. } => io::ErrorKind::NotFound,
+            PromptPalError::NotADirectory { .. } | PromptPalError::InvalidPattern { .. } => {
+                io::ErrorKind::InvalidInput
+            }
+            PromptPalError::PermissionDenied { .. } => io::ErrorKind::PermissionDenied,
+            PromptPalError::DecodeFailure { .. } => io::ErrorKind::InvalidData,
+            PromptPalError::Output { source } | PromptPalError::Io { source, .. } => source.kind(),
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// A non-fatal problem encountered while processing a file
+#[derive(Debug, Clone, Serialize)]
+pub struct Warning {
+    /// Path the warning relates to
+    pub path: PathBuf,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(path: impl Into<PathBuf>, message: impl Into<String>) -> Self {
+        Warning {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_io_classification() {
+        let err = PromptPalError::from_io(
+            "secret.txt",
+            io::Error::new(io::ErrorKind::PermissionDenied, "denied"),
+        );
+        assert!(matches!(err, PromptPalError::PermissionDenied { .. }));
+        assert_eq!(err.path(), Some(Path::new("secret.txt")));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::PermissionDenied);
+
+        let err = PromptPalError::from_io("a.bin", io::Error::other("boom"));
+        assert!(matches!(err, PromptPalError::Io { .. }));
+        assert_eq!(err.to_string(), "a.bin: boom");
+    }
+
+    #[test]
+    fn test_missing_root_converts_to_not_found() {
+        let err = PromptPalError::MissingRoot {
+            path: PathBuf::from("nope"),
+        };
+        assert_eq!(err.to_string(), "Directory not found: nope");
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -1,7 +1,22 @@
+use crate::error::{Result, Warning};
+use crate::lines::LineRange;
+use crate::walkdir::{scan_directory_filtered, FileFilter};
+use serde::Serialize;
 use std::fs;
 use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 
+/// How a file's content is presented in a prompt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentMode {
+    /// The content as read from disk
+    #[default]
+    Full,
+    /// Top-level signatures and doc comments only, see [`crate::outline`]
+    Outline,
+}
+
 /// Represents metadata and content information for a file
 #[derive(Debug, Clone)]
 pub struct FileInfo {
@@ -13,6 +28,14 @@ pub struct FileInfo {
     pub content: Option<String>,
     /// Whether the content is complete or just a preview
     pub is_content_complete: bool,
+    /// Token count of the content, if it has already been computed
+    pub tokens: Option<usize>,
+    /// How the content is presented
+    pub mode: ContentMode,
+    /// 1-based line number in the file of the first line of `content`
+    pub start_line: usize,
+    /// Lines of the file `content` is limited to, when only part of it was selected
+    pub range: Option<LineRange>,
 }
 
 impl FileInfo {
@@ -35,6 +58,10 @@ impl FileInfo {
             size,
             content,
             is_content_complete: is_complete,
+            tokens: None,
+            mode: ContentMode::Full,
+            start_line: 1,
+            range: None,
         })
     }
 
@@ -51,9 +78,18 @@ impl FileInfo {
             size,
             content,
             is_content_complete: true,
+            tokens: None,
+            mode: ContentMode::Full,
+            start_line: 1,
+            range: None,
         })
     }
 
+    /// Returns the file type used to label content blocks, based on the extension
+    pub fn file_type(&self) -> String {
+        detect_file_type(&self.path)
+    }
+
     /// Reads content from a file, either preview or full
     fn read_content(path: &Path, max_length: Option<usize>) -> io::Result<String> {
         let mut file = fs::File::open(path)?;
@@ -91,6 +127,14 @@ impl FileInfo {
     }
 }
 
+/// Detects a file's type from its extension, falling back to "text"
+pub fn detect_file_type(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_else(|| "text".to_string())
+}
+
 /// Gathers file information for all files in a directory
 ///
 /// # Arguments
@@ -98,21 +142,22 @@ impl FileInfo {
 /// * `preview_length` - Number of bytes to read for content previews (0 for no previews)
 ///
 /// # Returns
-/// * `io::Result<Vec<FileInfo>>` - Vector of file information or an error
-#[allow(dead_code)]
+/// * `Result<(Vec<FileInfo>, Vec<Warning>)>` - File information plus warnings for files
+///   that couldn't be read, or an error if the directory can't be scanned
 pub fn gather_file_info(
     target_dir: impl AsRef<Path>,
     preview_length: usize,
-) -> io::Result<Vec<FileInfo>> {
-    let paths = crate::walkdir::scan_directory(target_dir.as_ref().to_path_buf())?;
+) -> Result<(Vec<FileInfo>, Vec<Warning>)> {
+    let scan = scan_directory_filtered(target_dir.as_ref(), &FileFilter::default())?;
+    let mut warnings = scan.warnings;
 
     let mut file_infos = Vec::new();
-    for path in paths {
+    for path in scan.files {
         match FileInfo::with_preview(&path, preview_length) {
             Ok(info) => file_infos.push(info),
-            Err(e) => eprintln!("Error processing {}: {}", path.display(), e),
+            Err(e) => warnings.push(Warning::new(path, e.to_string())),
         }
     }
 
-    Ok(file_infos)
+    Ok((file_infos, warnings))
 }
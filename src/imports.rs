@@ -0,0 +1,611 @@
+use crate::fileinfo::detect_file_type;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Extensions tried, in order, for extensionless JS/TS import specifiers
+const JS_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs", "d.ts"];
+
+/// Files imported by each file, as found by [`follow_imports`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportGraph {
+    /// Every file reached from the entries, entries first, in the order they were reached
+    pub files: Vec<PathBuf>,
+    /// Resolved imports of each file in `files`, sorted by importing file
+    pub edges: BTreeMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl ImportGraph {
+    /// Returns true if `path` was reached from an entry
+    pub fn contains(&self, path: &Path) -> bool {
+        self.edges.contains_key(path)
+    }
+}
+
+/// An import statement as written in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Import {
+    /// Rust `mod name;`
+    RustMod(String),
+    /// Rust `use` path split into segments, e.g. `crate::render::Transform`
+    RustUse(Vec<String>),
+    /// Python module, with the number of leading dots for relative imports
+    Python { dots: usize, module: String },
+    /// JS/TS `import`/`require`/`export ... from` specifier
+    Js(String),
+    /// Go package import path
+    Go(String),
+    /// C/C++ `#include "..."`
+    Include(String),
+}
+
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("import patterns are valid"))
+}
+
+/// Parses the import statements of a file, based on its extension
+///
+/// Unsupported file types have no imports.
+pub fn parse_imports(path: &Path, content: &str) -> Vec<Import> {
+    static RUST_MOD: OnceLock<Regex> = OnceLock::new();
+    static RUST_USE: OnceLock<Regex> = OnceLock::new();
+    static PY_IMPORT: OnceLock<Regex> = OnceLock::new();
+    static PY_FROM: OnceLock<Regex> = OnceLock::new();
+    static JS: OnceLock<Regex> = OnceLock::new();
+    static GO_SINGLE: OnceLock<Regex> = OnceLock::new();
+    static GO_BLOCK: OnceLock<Regex> = OnceLock::new();
+    static GO_SPEC: OnceLock<Regex> = OnceLock::new();
+    static C_INCLUDE: OnceLock<Regex> = OnceLock::new();
+
+    let mut imports = Vec::new();
+    match detect_file_type(path).as_str() {
+        "rs" => {
+            let mods = regex(
+                &RUST_MOD,
+                r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+(\w+)\s*;",
+            );
+            imports.extend(
+                mods.captures_iter(content)
+                    .map(|c| Import::RustMod(c[1].into())),
+            );
+
+            let uses = regex(
+                &RUST_USE,
+                r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?use\s+([^;]+);",
+            );
+            for captures in uses.captures_iter(content) {
+                for path in expand_use_tree(&captures[1]) {
+                    imports.push(Import::RustUse(path));
+                }
+            }
+        }
+        "py" => {
+            let plain = regex(&PY_IMPORT, r"(?m)^\s*import\s+([\w., ]+)");
+            for captures in plain.captures_iter(content) {
+                for module in captures[1].split(',') {
+                    let module = module.split_whitespace().next().unwrap_or_default();
+                    if !module.is_empty() {
+                        imports.push(Import::Python {
+                            dots: 0,
+                            module: module.into(),
+                        });
+                    }
+                }
+            }
+
+            let from = regex(
+                &PY_FROM,
+                r"(?m)^\s*from\s+(\.*)([\w.]*)\s+import\s+\(?([\w, ]+)",
+            );
+            for captures in from.captures_iter(content) {
+                let dots = captures[1].len();
+                let module = captures[2].to_string();
+                // `from pkg import name` may name a submodule, so try each one too
+                for name in captures[3].split(',').map(str::trim) {
+                    let name = name.split_whitespace().next().unwrap_or_default();
+                    if !name.is_empty() && name != "*" {
+                        let submodule = if module.is_empty() {
+                            name.to_string()
+                        } else {
+                            format!("{}.{}", module, name)
+                        };
+                        imports.push(Import::Python {
+                            dots,
+                            module: submodule,
+                        });
+                    }
+                }
+                if !module.is_empty() {
+                    imports.push(Import::Python { dots, module });
+                }
+            }
+        }
+        "js" | "jsx" | "ts" | "tsx" | "mjs" | "cjs" | "mts" | "cts" => {
+            let js = regex(
+                &JS,
+                r#"(?:\bimport\s*(?:[\w*{}\s,$]+\s*from\s*)?|\bexport\s+[\w*{}\s,$]+\s*from\s*|\brequire\s*\(\s*|\bimport\s*\(\s*)["']([^"']+)["']"#,
+            );
+            imports.extend(js.captures_iter(content).map(|c| Import::Js(c[1].into())));
+        }
+        "go" => {
+            let single = regex(&GO_SINGLE, r#"(?m)^\s*import\s+(?:[\w.]+\s+)?"([^"]+)""#);
+            imports.extend(
+                single
+                    .captures_iter(content)
+                    .map(|c| Import::Go(c[1].into())),
+            );
+
+            let block = regex(&GO_BLOCK, r"(?ms)^\s*import\s*\((.*?)\)");
+            let spec = regex(&GO_SPEC, r#""([^"]+)""#);
+            for captures in block.captures_iter(content) {
+                imports.extend(
+                    spec.captures_iter(&captures[1])
+                        .map(|c| Import::Go(c[1].into())),
+                );
+            }
+        }
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => {
+            let include = regex(&C_INCLUDE, r#"(?m)^\s*#\s*include\s*"([^"]+)""#);
+            imports.extend(
+                include
+                    .captures_iter(content)
+                    .map(|c| Import::Include(c[1].into())),
+            );
+        }
+        _ => {}
+    }
+    imports
+}
+
+/// Expands a Rust use tree such as `crate::a::{b, c::D}` into its full paths
+fn expand_use_tree(tree: &str) -> Vec<Vec<String>> {
+    let mut paths = Vec::new();
+    expand_into(tree, Vec::new(), &mut paths);
+    paths
+}
+
+fn expand_into(tree: &str, prefix: Vec<String>, paths: &mut Vec<Vec<String>>) {
+    let segments = |text: &str| -> Vec<String> {
+        text.split("::")
+            .map(str::trim)
+            .filter(|s| !s.is_empty() && *s != "*")
+            .map(str::to_string)
+            .collect()
+    };
+
+    let mut path = prefix;
+    let Some(open) = tree.find('{') else {
+        // Drop any `as` rename
+        let tree = tree.split(" as ").next().unwrap_or(tree);
+        path.extend(segments(tree));
+        if !path.is_empty() {
+            paths.push(path);
+        }
+        return;
+    };
+
+    path.extend(segments(&tree[..open]));
+    let inner = &tree[open + 1..tree.rfind('}').unwrap_or(tree.len())];
+
+    // Split on top-level commas only
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                expand_into(&inner[start..i], path.clone(), paths);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    expand_into(&inner[start..], path, paths);
+}
+
+/// Resolves import specifiers to files in a scanned tree
+struct Resolver<'a> {
+    /// Scanned files, relative to the root
+    files: &'a HashSet<PathBuf>,
+    /// Go module paths from `go.mod` files, mapped to the directory they live in
+    go_modules: Vec<(String, PathBuf)>,
+    /// Go files grouped by directory
+    go_packages: HashMap<PathBuf, Vec<PathBuf>>,
+    /// Library crates in the tree, by the name they're used with, mapped to their `src` directory
+    rust_crates: HashMap<String, PathBuf>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(root: &Path, files: &'a HashSet<PathBuf>) -> Self {
+        let mut go_modules = Vec::new();
+        let mut go_packages: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        let mut rust_crates = HashMap::new();
+        for file in files {
+            if file.file_name().is_some_and(|name| name == "Cargo.toml") {
+                let dir = file.parent().unwrap_or(Path::new(""));
+                let name = fs::read_to_string(root.join(file)).ok().and_then(|text| {
+                    let package = text.split("[package]").nth(1)?;
+                    let package = package.split("\n[").next().unwrap_or(package);
+                    package.lines().find_map(|line| {
+                        let (key, value) = line.split_once('=')?;
+                        (key.trim() == "name").then(|| value.trim().trim_matches('"').to_string())
+                    })
+                });
+                if let Some(name) = name.filter(|_| files.contains(&dir.join("src/lib.rs"))) {
+                    rust_crates.insert(name.replace('-', "_"), dir.join("src"));
+                }
+            }
+            if file.file_name().is_some_and(|name| name == "go.mod") {
+                let module = fs::read_to_string(root.join(file)).ok().and_then(|text| {
+                    text.lines()
+                        .find_map(|line| line.trim().strip_prefix("module "))
+                        .map(|module| module.trim().to_string())
+                });
+                if let Some(module) = module {
+                    let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+                    go_modules.push((module, dir));
+                }
+            }
+            let name = file.to_string_lossy();
+            if name.ends_with(".go") && !name.ends_with("_test.go") {
+                let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+                go_packages.entry(dir).or_default().push(file.clone());
+            }
+        }
+        for package in go_packages.values_mut() {
+            package.sort();
+        }
+
+        Resolver {
+            files,
+            go_modules,
+            go_packages,
+            rust_crates,
+        }
+    }
+
+    fn existing(&self, candidates: impl IntoIterator<Item = PathBuf>) -> Option<PathBuf> {
+        candidates
+            .into_iter()
+            .map(|candidate| normalize(&candidate))
+            .find(|candidate| self.files.contains(candidate))
+    }
+
+    /// Returns the files `import`, found in `from`, refers to
+    fn resolve(&self, from: &Path, import: &Import) -> Vec<PathBuf> {
+        let dir = from.parent().unwrap_or(Path::new(""));
+        match import {
+            Import::RustMod(name) => {
+                let module_dir = rust_module_dir(from);
+                self.existing([
+                    module_dir.join(format!("{}.rs", name)),
+                    module_dir.join(name).join("mod.rs"),
+                ])
+                .into_iter()
+                .collect()
+            }
+            Import::RustUse(segments) => self.resolve_rust_use(from, segments),
+            Import::Python { dots, module } => {
+                let relative = module.replace('.', "/");
+                let mut bases = Vec::new();
+                if *dots > 0 {
+                    let mut base = dir.to_path_buf();
+                    for _ in 1..*dots {
+                        base = base.parent().unwrap_or(Path::new("")).to_path_buf();
+                    }
+                    bases.push(base);
+                } else {
+                    bases.push(PathBuf::new());
+                    bases.push(dir.to_path_buf());
+                }
+                bases
+                    .iter()
+                    .find_map(|base| {
+                        self.existing([
+                            base.join(format!("{}.py", relative)),
+                            base.join(&relative).join("__init__.py"),
+                        ])
+                    })
+                    .into_iter()
+                    .collect()
+            }
+            Import::Js(specifier) => {
+                if !specifier.starts_with('.') {
+                    return Vec::new();
+                }
+                let base = dir.join(specifier);
+                let mut candidates = vec![base.clone()];
+                for ext in JS_EXTENSIONS {
+                    candidates.push(PathBuf::from(format!("{}.{}", base.display(), ext)));
+                }
+                // TS sources are imported with the `.js` extension they compile to
+                if let Some(stem) = specifier.strip_suffix(".js") {
+                    for ext in ["ts", "tsx"] {
+                        candidates.push(dir.join(format!("{}.{}", stem, ext)));
+                    }
+                }
+                for ext in JS_EXTENSIONS {
+                    candidates.push(base.join(format!("index.{}", ext)));
+                }
+                self.existing(candidates).into_iter().collect()
+            }
+            Import::Go(import_path) => {
+                let package_dir = self.go_modules.iter().find_map(|(module, dir)| {
+                    let rest = import_path.strip_prefix(module.as_str())?;
+                    if !rest.is_empty() && !rest.starts_with('/') {
+                        return None;
+                    }
+                    Some(dir.join(rest.trim_start_matches('/')))
+                });
+                package_dir
+                    .and_then(|dir| self.go_packages.get(&normalize(&dir)))
+                    .cloned()
+                    .unwrap_or_default()
+            }
+            Import::Include(header) => {
+                if let Some(found) = self.existing([dir.join(header), PathBuf::from(header)]) {
+                    return vec![found];
+                }
+                // Fall back to a unique file anywhere in the tree ending with the header path
+                let mut matches: Vec<_> = self
+                    .files
+                    .iter()
+                    .filter(|file| file.ends_with(header))
+                    .collect();
+                matches.sort();
+                match matches.as_slice() {
+                    [only] => vec![(*only).clone()],
+                    _ => Vec::new(),
+                }
+            }
+        }
+    }
+
+    /// Resolves a `use` path to the longest module prefix that exists as a file
+    fn resolve_rust_use(&self, from: &Path, segments: &[String]) -> Vec<PathBuf> {
+        let (base, rest) = match segments.first().map(String::as_str) {
+            Some("crate") => (self.rust_crate_root(from), &segments[1..]),
+            Some(name) if self.rust_crates.contains_key(name) => {
+                (self.rust_crates[name].clone(), &segments[1..])
+            }
+            Some("self") => (rust_module_dir(from), &segments[1..]),
+            Some("super") => {
+                let mut base = rust_module_dir(from);
+                let mut rest = &segments[1..];
+                base = base.parent().unwrap_or(Path::new("")).to_path_buf();
+                while rest.first().is_some_and(|s| s == "super") {
+                    base = base.parent().unwrap_or(Path::new("")).to_path_buf();
+                    rest = &rest[1..];
+                }
+                (base, rest)
+            }
+            _ => return Vec::new(),
+        };
+
+        for len in (1..=rest.len()).rev() {
+            let module = rest[..len].iter().collect::<PathBuf>();
+            let found = self.existing([
+                base.join(&module).with_extension("rs"),
+                base.join(&module).join("mod.rs"),
+            ]);
+            if let Some(found) = found {
+                return vec![found];
+            }
+        }
+        // Items at the crate root, e.g. `use crate::Foo`
+        if segments.first().is_some_and(|s| s == "crate") {
+            return self
+                .existing([base.join("lib.rs"), base.join("main.rs")])
+                .into_iter()
+                .collect();
+        }
+        if segments
+            .first()
+            .is_some_and(|s| self.rust_crates.contains_key(s))
+        {
+            return self.existing([base.join("lib.rs")]).into_iter().collect();
+        }
+        Vec::new()
+    }
+
+    /// Returns the directory of the crate root (`lib.rs` or `main.rs`) above `from`
+    fn rust_crate_root(&self, from: &Path) -> PathBuf {
+        from.ancestors()
+            .skip(1)
+            .find(|dir| {
+                self.files.contains(&dir.join("lib.rs"))
+                    || self.files.contains(&dir.join("main.rs"))
+            })
+            .unwrap_or(Path::new("src"))
+            .to_path_buf()
+    }
+}
+
+/// Returns the directory a Rust file's child modules live in
+fn rust_module_dir(path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some("main.rs" | "lib.rs" | "mod.rs") => dir.to_path_buf(),
+        _ => dir.join(path.file_stem().unwrap_or_default()),
+    }
+}
+
+/// Removes `.` and resolves `..` components without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Follows imports from entry files through a scanned tree
+///
+/// # Arguments
+/// * `root` - Directory the files were scanned from
+/// * `files` - Scanned files, as returned by the walker (i.e. prefixed with `root`)
+/// * `entries` - Files to start from, relative to `root` or prefixed with it
+/// * `depth` - How many import hops to follow; `None` follows the full closure
+///
+/// # Returns
+/// * The reached files and their imports, with paths prefixed by `root` like `files`.
+///   Entries that aren't among `files` are returned as the error.
+pub fn follow_imports(
+    root: &Path,
+    files: &[PathBuf],
+    entries: &[PathBuf],
+    depth: Option<usize>,
+) -> Result<ImportGraph, Vec<PathBuf>> {
+    let relative: HashSet<PathBuf> = files
+        .iter()
+        .filter_map(|file| file.strip_prefix(root).ok())
+        .map(Path::to_path_buf)
+        .collect();
+    let resolver = Resolver::new(root, &relative);
+
+    let mut queue = VecDeque::new();
+    let mut missing = Vec::new();
+    for entry in entries {
+        let entry = normalize(entry.strip_prefix(root).unwrap_or(entry));
+        if relative.contains(&entry) {
+            queue.push_back((entry, 0));
+        } else {
+            missing.push(root.join(entry));
+        }
+    }
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+
+    let mut graph = ImportGraph::default();
+    let mut seen = HashSet::new();
+    while let Some((file, hops)) = queue.pop_front() {
+        if !seen.insert(file.clone()) {
+            continue;
+        }
+        let mut imports = Vec::new();
+        if depth.is_none_or(|depth| hops < depth) {
+            let content = fs::read_to_string(root.join(&file)).unwrap_or_default();
+            for import in parse_imports(&file, &content) {
+                for target in resolver.resolve(&file, &import) {
+                    if target != file && !imports.contains(&target) {
+                        imports.push(target);
+                    }
+                }
+            }
+            for target in &imports {
+                if !seen.contains(target) {
+                    queue.push_back((target.clone(), hops + 1));
+                }
+            }
+        }
+        graph.files.push(root.join(&file));
+        graph.edges.insert(
+            root.join(&file),
+            imports.iter().map(|target| root.join(target)).collect(),
+        );
+    }
+
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve_all(files: &[&str], from: &str, content: &str) -> Vec<PathBuf> {
+        let files: HashSet<PathBuf> = files.iter().map(PathBuf::from).collect();
+        let resolver = Resolver::new(Path::new("/nonexistent"), &files);
+        parse_imports(Path::new(from), content)
+            .iter()
+            .flat_map(|import| resolver.resolve(Path::new(from), import))
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_use_tree() {
+        assert_eq!(
+            expand_use_tree("crate::render::{self, Transform as T, tree::{FileNode}}"),
+            vec![
+                vec!["crate", "render", "self"],
+                vec!["crate", "render", "Transform"],
+                vec!["crate", "render", "tree", "FileNode"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_rust() {
+        let files = [
+            "src/main.rs",
+            "src/cli.rs",
+            "src/render/mod.rs",
+            "src/render/xml.rs",
+        ];
+        let resolved = resolve_all(
+            &files,
+            "src/main.rs",
+            "mod cli;\npub mod render;\nuse crate::render::xml::XmlGenerator;\nuse std::io;",
+        );
+        assert_eq!(
+            resolved,
+            vec![
+                PathBuf::from("src/cli.rs"),
+                PathBuf::from("src/render/mod.rs"),
+                PathBuf::from("src/render/xml.rs"),
+            ]
+        );
+
+        let resolved = resolve_all(&files, "src/render/xml.rs", "use super::super::cli::Cli;");
+        assert_eq!(resolved, vec![PathBuf::from("src/cli.rs")]);
+    }
+
+    #[test]
+    fn test_resolve_python_js_and_c() {
+        let files = ["app/main.py", "app/models/user.py", "app/utils.py"];
+        let resolved = resolve_all(
+            &files,
+            "app/main.py",
+            "import os\nfrom app.models import user\nfrom .utils import slugify",
+        );
+        assert_eq!(
+            resolved,
+            vec![
+                PathBuf::from("app/models/user.py"),
+                PathBuf::from("app/utils.py"),
+            ]
+        );
+
+        let files = ["web/index.ts", "web/api/client.ts", "web/ui/index.tsx"];
+        let resolved = resolve_all(
+            &files,
+            "web/index.ts",
+            "import { get } from './api/client.js';\nconst ui = require(\"./ui\");\nimport React from 'react';",
+        );
+        assert_eq!(
+            resolved,
+            vec![
+                PathBuf::from("web/api/client.ts"),
+                PathBuf::from("web/ui/index.tsx"),
+            ]
+        );
+
+        let files = ["src/main.c", "include/util.h"];
+        let resolved = resolve_all(
+            &files,
+            "src/main.c",
+            "#include <stdio.h>\n#include \"util.h\"",
+        );
+        assert_eq!(resolved, vec![PathBuf::from("include/util.h")]);
+    }
+}
@@ -1,9 +1,44 @@
+pub mod apply;
+pub mod ask;
+pub mod builder;
+pub mod cache;
 pub mod cli;
+pub mod config;
+pub mod diff;
+pub mod error;
 pub mod fileinfo;
+pub mod imports;
+pub mod lines;
+pub mod markdown;
+pub mod mcp;
+pub mod outline;
+pub mod pick;
+pub mod prompts;
+pub mod rank;
+pub mod redact;
+pub mod render;
+pub mod report;
+pub mod serve;
+pub mod service;
+pub mod spec;
+pub mod strip;
 pub mod tokenizer;
+mod tree;
+pub mod unpack;
 pub mod walkdir;
+pub mod watch;
+pub mod xml;
 
 // Re-export commonly used items
+pub use builder::{Prompt, PromptBuilder, PromptReport};
+pub use error::{PromptPalError, Warning};
 pub use fileinfo::{gather_file_info, FileInfo};
-pub use tokenizer::count_tokens;
+pub use imports::{follow_imports, ImportGraph};
+pub use rank::{Relevance, RelevanceIndex};
+pub use redact::{Redaction, RedactionRules, SecretScanner};
+pub use render::{OutputFormat, RenderOptions};
+pub use report::TokenReport;
+pub use spec::FileSpec;
+pub use tokenizer::{count_tokens, Encoding};
 pub use walkdir::scan_directory;
+pub use xml::XmlGenerator;
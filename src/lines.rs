@@ -0,0 +1,119 @@
+use std::fmt;
+
+/// A 1-based, inclusive range of lines in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    /// Returns the lines of `content` in the range, with their line endings
+    pub fn slice<'a>(&self, content: &'a str) -> &'a str {
+        let mut ends = content.split_inclusive('\n').scan(0, |offset, line| {
+            *offset += line.len();
+            Some(*offset)
+        });
+        let from = match self.start {
+            0 | 1 => 0,
+            start => ends.nth(start - 2).unwrap_or(content.len()),
+        };
+        let to = ends
+            .nth(self.end.saturating_sub(self.start.max(1)))
+            .unwrap_or(content.len());
+        &content[from..to.max(from)]
+    }
+}
+
+impl fmt::Display for LineRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// Prefixes each line of `content` with its line number
+///
+/// Numbers are right-aligned to the width of the largest one and separated from
+/// the line by `" | "`, so every line of a block lines up.
+///
+/// # Arguments
+/// * `content` - Text to number
+/// * `start_line` - 1-based line number of the first line of the source `content` came from
+/// * `sources` - 0-based offset from `start_line` of each line, for content that skips
+///   or adds lines (stripped or outlined files); lines mapped to `None` get a blank
+///   gutter. When absent, lines are numbered consecutively.
+pub fn number_lines(content: &str, start_line: usize, sources: Option<&[Option<usize>]>) -> String {
+    let lines: Vec<_> = content.lines().collect();
+    let number = |index: usize| match sources {
+        Some(sources) => sources
+            .get(index)
+            .copied()
+            .flatten()
+            .map(|offset| start_line + offset),
+        None => Some(start_line + index),
+    };
+    let width = (0..lines.len())
+        .filter_map(number)
+        .max()
+        .unwrap_or(start_line)
+        .to_string()
+        .len();
+
+    let mut numbered = String::with_capacity(content.len() + lines.len() * (width + 3));
+    for (index, line) in lines.iter().enumerate() {
+        let gutter = match number(index) {
+            Some(number) => format!("{:>width$} |", number),
+            None => format!("{:width$} |", ""),
+        };
+        numbered.push_str(&gutter);
+        if !line.is_empty() {
+            numbered.push(' ');
+            numbered.push_str(line);
+        }
+        numbered.push('\n');
+    }
+    if !content.ends_with('\n') {
+        numbered.pop();
+    }
+    numbered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_line_range() {
+        let content = "one\ntwo\nthree\nfour";
+        assert_eq!(
+            LineRange { start: 2, end: 3 }.slice(content),
+            "two\nthree\n"
+        );
+        assert_eq!(LineRange { start: 1, end: 1 }.slice(content), "one\n");
+        assert_eq!(LineRange { start: 3, end: 9 }.slice(content), "three\nfour");
+        assert_eq!(LineRange { start: 7, end: 9 }.slice(content), "");
+    }
+
+    #[test]
+    fn test_number_lines() {
+        let content = (1..=10)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        let numbered = number_lines(&content, 1, None);
+        assert!(numbered.starts_with(" 1 | line 1\n 2 | line 2\n"));
+        assert!(numbered.ends_with("10 | line 10\n"));
+
+        // Chunks keep the numbering of the file they came from
+        assert_eq!(number_lines("a\n\nc", 99, None), " 99 | a\n100 |\n101 | c");
+
+        // Lines mapped back to their source, with a blank gutter for added lines
+        assert_eq!(
+            number_lines(
+                "fn a() {\n    ...\n}\n",
+                1,
+                Some(&[Some(4), None, Some(11)])
+            ),
+            " 5 | fn a() {\n   |     ...\n12 | }\n"
+        );
+    }
+}
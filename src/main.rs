@@ -1,48 +1,291 @@
-mod cli;
-mod fileinfo;
-mod tokenizer;
-mod walkdir;
-mod xml;
-
-use clap::Parser;
-use cli::Cli;
-use fileinfo::FileInfo;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use promptpal::apply::{self, Apply};
+use promptpal::ask::ChatClient;
+use promptpal::cache::Cache;
+use promptpal::cli::{
+    ApplyArgs, AskArgs, CacheArgs, CacheCommand, Cli, Command, CountArgs, PackArgs, PickArgs,
+    ReportArgs, ScanArgs, ServeArgs, ServiceArgs, TreeArgs, UnpackArgs,
+};
+use promptpal::config::{Config, Settings};
+use promptpal::fileinfo::FileInfo;
+use promptpal::mcp::McpServer;
+use promptpal::pick::{self, Picker};
+use promptpal::prompts::PromptLibrary;
+use promptpal::report::TokenReport;
+use promptpal::serve;
+use promptpal::service::ContextService;
+use promptpal::tokenizer::context_size;
+use promptpal::unpack::{self, Unpack};
+use promptpal::walkdir::{scan_directory_filtered, FileFilter};
+use promptpal::watch::PromptWatcher;
+use promptpal::{FileSpec, OutputFormat, PromptBuilder, PromptReport, RedactionRules, Warning};
+use std::collections::BTreeMap;
+use std::env;
 use std::error::Error;
-use tokenizer::count_tokens;
-use walkdir::scan_directory;
-use xml::XmlGenerator;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
 
     // Enable debug logging if requested
     if cli.debug > 0 {
         println!("Debug mode enabled (level: {})", cli.debug);
     }
+    let debug = cli.debug;
 
-    // Scan the directory
-    println!("Scanning directory: {}", cli.directory.display());
-    let files = scan_directory(&cli.directory)?;
+    // Top-level flags only apply without a subcommand; -v works either way
+    if let Some((name, _)) = matches.subcommand() {
+        if let Some(id) = matches
+            .ids()
+            .find(|id| id.as_str() != "debug" && from_cli(&matches, id.as_str()))
+        {
+            let mut command = Cli::command();
+            let flag = command
+                .get_arguments()
+                .find(|arg| arg.get_id() == id)
+                .and_then(|arg| arg.get_long())
+                .unwrap_or(id.as_str())
+                .to_string();
+            command
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("--{} must come after the '{}' subcommand", flag, name),
+                )
+                .exit();
+        }
+    }
+
+    // Config values fill in flags that weren't given on the command line
+    let matches = matches
+        .subcommand()
+        .map_or(&matches, |(_, matches)| matches);
+    match cli.into_command() {
+        Command::Pack(mut args) => {
+            configure_pack(&mut args, debug, matches)?;
+            let directory = args.scan.directory();
+            println!("Scanning directory: {}", directory.display());
+            if args.redact_dry_run {
+                run_redact_dry_run(&args)
+            } else if args.watch {
+                run_watch(&args)
+            } else {
+                run_pack(&args)
+            }
+        }
+        Command::Pick(mut args) => {
+            configure_pack(&mut args.pack, debug, matches)?;
+            run_pick(&mut args)
+        }
+        Command::Ask(mut args) => {
+            configure_pack(&mut args.pack, debug, matches)?;
+            run_ask(&args).await
+        }
+        Command::Count(mut args) => {
+            if let Some(settings) = load_settings(&args.scan, debug)? {
+                apply_scan_settings(&mut args.scan, &settings, matches);
+            }
+            run_count(&args)
+        }
+        Command::Tree(mut args) => {
+            if let Some(settings) = load_settings(&args.scan, debug)? {
+                apply_scan_settings(&mut args.scan, &settings, matches);
+            }
+            run_tree(&args)
+        }
+        Command::Report(mut args) => {
+            let settings = load_settings(&args.scan, debug)?.unwrap_or_default();
+            apply_scan_settings(&mut args.scan, &settings, matches);
+            args.model = args.model.take().or(settings.model.clone());
+            run_report(&args, settings.redactions.as_deref())
+        }
+        Command::Unpack(args) => run_unpack(&args),
+        Command::Apply(args) => run_apply(&args),
+        Command::Cache(args) => run_cache(&args),
+        Command::Serve(mut args) => {
+            let service = context_service(&mut args.service, debug, matches)?;
+            run_serve(&args, service).await
+        }
+        Command::Mcp(mut args) => {
+            let service = context_service(&mut args, debug, matches)?;
+            run_mcp(service)
+        }
+    }
+}
+
+/// Fills in pack options from the configuration files and resolves the instructions
+fn configure_pack(
+    args: &mut PackArgs,
+    debug: u8,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let settings = load_settings(&args.scan, debug)?;
+    if let Some(settings) = &settings {
+        apply_scan_settings(&mut args.scan, settings, matches);
+        apply_pack_settings(args, settings, matches)?;
+    }
+    let prompts = settings.as_ref().and_then(|s| s.prompts.as_deref());
+    resolve_instructions(args, prompts)
+}
+
+/// Lets the user pick files in a terminal UI, then renders the prompt from them
+fn run_pick(args: &mut PickArgs) -> Result<(), Box<dyn Error>> {
+    if !io::stderr().is_terminal() {
+        return Err("pick needs an interactive terminal".into());
+    }
+    // Listed files are preselected rather than limiting what can be picked
+    let mut listed = std::mem::take(&mut args.pack.files);
+    if let Some(list) = args.pack.files_from.take() {
+        listed.extend(FileSpec::read_list(&list)?);
+    }
+    // The picker works with paths relative to the scanned directory
+    let directory = args.pack.scan.directory().to_path_buf();
+    let relative = |path: PathBuf| match path.strip_prefix(&directory) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => path,
+    };
+    for spec in &mut listed {
+        spec.path = relative(std::mem::take(&mut spec.path));
+    }
+    let cache = open_cache(&args.pack.scan);
+    let candidates = pack_builder(&args.pack, cache.as_ref())?
+        .candidates()?
+        .into_iter()
+        .map(|mut file| {
+            file.path = relative(file.path);
+            file
+        })
+        .collect();
+    save_cache(cache.as_deref());
+    let budget = args
+        .pack
+        .budget
+        .unwrap_or_else(|| context_size(args.pack.model.as_deref()));
+
+    let Some(specs) = pick::run(Picker::new(candidates, budget, &listed))? else {
+        println!("Nothing picked");
+        return Ok(());
+    };
+    if specs.is_empty() {
+        return Err("No files were selected".into());
+    }
+    if let Some(path) = &args.save {
+        let list: String = specs.iter().map(|spec| format!("{}\n", spec)).collect();
+        std::fs::write(path, format!("# Picked with promptpal pick\n{}", list))?;
+        println!("Selection saved to: {}", path.display());
+    }
+    args.pack.files = specs;
+    if args.pack.watch {
+        run_watch(&args.pack)
+    } else {
+        run_pack(&args.pack)
+    }
+}
+
+/// Renders the prompt and streams the model's answer to stdout or the output file
+async fn run_ask(args: &AskArgs) -> Result<(), Box<dyn Error>> {
+    let pack = &args.pack;
+    if pack.watch || pack.redact_dry_run {
+        return Err("ask can't be combined with --watch or --redact-dry-run".into());
+    }
+    let Some(model) = &pack.model else {
+        return Err("ask needs a model: pass --model or set model in a configuration file".into());
+    };
+    let cache = open_cache(&pack.scan);
+    let prompt = pack_builder(pack, cache.as_ref())?.build()?;
+    save_cache(cache.as_deref());
+
+    let mut client = ChatClient::new(&args.endpoint, model);
+    if let Some(key) = env::var(&args.api_key_env)
+        .ok()
+        .filter(|key| !key.is_empty())
+    {
+        client = client.api_key(key);
+    }
+    if let Some(system) = &args.system {
+        client = client.system(system);
+    }
+    if let Some(temperature) = args.temperature {
+        client = client.temperature(temperature);
+    }
+
+    // Status goes to stderr, so the answer can be piped
+    let report = &prompt.report;
+    eprintln!(
+        "Sending ~{} tokens from {} files to {} ({})",
+        report.prompt_tokens,
+        report.files.len(),
+        client.url(),
+        model
+    );
+    if !report.skipped.is_empty() {
+        eprintln!("Skipped {} files to fit the budget", report.skipped.len());
+    }
+    if !report.redactions.is_empty() {
+        eprintln!("Redacted {} secrets", report.redactions.len());
+    }
+
+    let stream = !args.no_stream;
+    let answer = if let Some(output_path) = &pack.output {
+        let file = BufWriter::new(File::create(output_path)?);
+        let answer = client.ask(&prompt.text, stream, file).await?;
+        eprintln!("Answer written to: {}", output_path.display());
+        answer
+    } else {
+        let answer = client.ask(&prompt.text, stream, io::stdout()).await?;
+        println!();
+        answer
+    };
+    if answer.finish_reason.as_deref() == Some("length") {
+        eprintln!("Warning: the answer was cut off at the model's output limit");
+    }
+    Ok(())
+}
+
+/// Counts the tokens in a preview of each file
+fn run_count(args: &CountArgs) -> Result<(), Box<dyn Error>> {
+    let scan_args = &args.scan;
+    let directory = scan_args.directory();
+    println!("Scanning directory: {}", directory.display());
+
+    let filter = FileFilter::new(&scan_args.include, &scan_args.exclude)?
+        .allow_sensitive(scan_args.allow_sensitive);
+    let scan = scan_directory_filtered(directory, &filter)?;
+    let mut warnings = scan.warnings;
+    let cache = open_cache(scan_args);
 
     // Process each file
-    let mut results = Vec::new();
     let mut total_tokens = 0;
-    for file in files.iter() {
-        let file_info = if cli.xml {
-            FileInfo::with_full_content(file)? // Full content for XML output
-        } else {
-            FileInfo::with_preview(file, 1024)? // Preview for token counting only
+    for file in scan.files.iter() {
+        // Preview for token counting only
+        let file_info = match FileInfo::with_preview(file, 1024) {
+            Ok(info) => info,
+            Err(e) => {
+                warnings.push(Warning::new(file, e.to_string()));
+                continue;
+            }
         };
 
-        let (token_count, _) = count_tokens(&file_info.content.clone().unwrap_or_default(), None);
-        if cli.per_file {
+        let preview = file_info.content.as_deref().unwrap_or_default();
+        let token_count = match &cache {
+            Some(cache) => cache.tokens(scan_args.encoding, preview),
+            None => scan_args.encoding.count(preview),
+        };
+        if args.per_file {
             println!("File: {}, Token count: ~{}", file.display(), token_count);
         }
         total_tokens += token_count;
-
-        results.push(file_info);
     }
+    save_cache(cache.as_deref());
+
+    print_warnings(&warnings);
+    print_sensitive_note(&scan.sensitive);
 
     // Always display total token count
     println!(
@@ -50,16 +293,645 @@ async fn main() -> Result<(), Box<dyn Error>> {
         total_tokens
     );
 
-    // Generate XML if requested
-    if cli.xml {
-        let xml = XmlGenerator::generate(&results, cli.instructions.as_deref().unwrap_or(""));
-        if let Some(output_path) = cli.output {
-            std::fs::write(&output_path, xml)?;
-            println!("XML output written to: {}", output_path.display());
+    Ok(())
+}
+
+/// Prints the tree view of the files a prompt would include
+fn run_tree(args: &TreeArgs) -> Result<(), Box<dyn Error>> {
+    let scan = &args.scan;
+    let mut builder = PromptBuilder::new()
+        .root(scan.directory())
+        .tokenizer(scan.encoding)
+        .allow_sensitive(scan.allow_sensitive)
+        .annotate_tree(args.annotate_tree);
+    for pattern in &scan.include {
+        builder = builder.include(pattern);
+    }
+    for pattern in &scan.exclude {
+        builder = builder.exclude(pattern);
+    }
+    let cache = open_cache(scan);
+    if let Some(cache) = &cache {
+        builder = builder.cache(cache.clone());
+    }
+    let tree = builder.tree()?;
+    save_cache(cache.as_deref());
+
+    if let Some(output_path) = &args.output {
+        std::fs::write(output_path, tree)?;
+        println!("Tree view written to: {}", output_path.display());
+    } else {
+        print!("{}", tree);
+    }
+    Ok(())
+}
+
+/// Writes the files in an LLM response back under the target directory
+fn run_unpack(args: &UnpackArgs) -> Result<(), Box<dyn Error>> {
+    let response = read_input(&args.input)?;
+    let files = unpack::parse_response(&response);
+    if files.is_empty() {
+        return Err(format!(
+            "No <file> elements with a <path> and <content> found in {}",
+            args.input.display()
+        )
+        .into());
+    }
+
+    let plan = Unpack::plan(&args.directory, files)?;
+    for change in &plan.changes {
+        if change.is_unchanged() {
+            println!("Unchanged: {}", change.path.display());
         } else {
-            println!("XML output:\n{}", xml);
+            print!("{}", change.diff());
+        }
+    }
+    print_warnings(&plan.warnings);
+
+    let changed = plan.changes.iter().filter(|c| !c.is_unchanged()).count();
+    if args.dry_run {
+        println!("\nDry run: {} file(s) would be written", changed);
+        return Ok(());
+    }
+    let backup_dir = unpack::backup_dir(&args.directory);
+    let backups = plan.write((!args.no_backup).then_some(backup_dir.as_path()))?;
+    println!(
+        "\nWrote {} file(s) under {}",
+        changed,
+        args.directory.display()
+    );
+    print_backup_note(&backups, &backup_dir);
+    Ok(())
+}
+
+/// Applies the diffs or SEARCH/REPLACE blocks in an LLM response, all or nothing
+fn run_apply(args: &ApplyArgs) -> Result<(), Box<dyn Error>> {
+    let response = read_input(&args.input)?;
+    let patches = apply::parse_patches(&response);
+    if patches.is_empty() {
+        return Err(format!(
+            "No unified diffs or SEARCH/REPLACE blocks found in {}",
+            args.input.display()
+        )
+        .into());
+    }
+
+    let plan = Apply::plan(&args.directory, patches)?;
+    for change in &plan.changes {
+        print!("{}", change.diff());
+    }
+    if !plan.failures.is_empty() {
+        eprintln!("\nFailed hunks:");
+        for failure in &plan.failures {
+            eprintln!("  {}", failure);
+        }
+        return Err(format!(
+            "{} of {} hunk(s) failed to apply; no files were changed",
+            plan.failures.len(),
+            plan.failures.len() + plan.applied
+        )
+        .into());
+    }
+
+    if args.dry_run {
+        println!(
+            "\nDry run: {} hunk(s) would change {} file(s)",
+            plan.applied,
+            plan.changes.len()
+        );
+        return Ok(());
+    }
+    let backup_dir = unpack::backup_dir(&args.directory);
+    let backups = plan.write((!args.no_backup).then_some(backup_dir.as_path()))?;
+    println!(
+        "\nApplied {} hunk(s) to {} file(s) under {}",
+        plan.applied,
+        plan.changes.len(),
+        args.directory.display()
+    );
+    print_backup_note(&backups, &backup_dir);
+    Ok(())
+}
+
+/// Sets up the service behind `serve` and `mcp` from the configuration files and flags
+fn context_service(
+    args: &mut ServiceArgs,
+    debug: u8,
+    matches: &ArgMatches,
+) -> Result<ContextService, Box<dyn Error>> {
+    let settings = load_settings(&args.scan, debug)?;
+    if let Some(settings) = &settings {
+        apply_scan_settings(&mut args.scan, settings, matches);
+    }
+    let settings = settings.unwrap_or_default();
+    let scan = &args.scan;
+    // Flags override the configuration, which requests can then only narrow
+    let settings = Settings {
+        include: scan.include.clone(),
+        exclude: scan.exclude.clone(),
+        encoding: Some(scan.encoding),
+        allow_sensitive: Some(scan.allow_sensitive),
+        model: args.model.clone().or(settings.model.clone()),
+        budget: args.budget.or(settings.budget),
+        redact: Some(!flag(
+            matches,
+            ("no_redact", "redact"),
+            args.no_redact,
+            settings.redact.map(|redact| !redact),
+        )),
+        redactions: args.redactions.clone().or(settings.redactions.clone()),
+        ..settings
+    };
+    let mut service = ContextService::new(scan.directory(), settings)?;
+    if let Some(cache) = open_cache(scan) {
+        service = service.cache(cache);
+    }
+    Ok(service)
+}
+
+/// Serves the HTTP/JSON API until interrupted
+async fn run_serve(args: &ServeArgs, service: ContextService) -> Result<(), Box<dyn Error>> {
+    let listener = tokio::net::TcpListener::bind((args.host, args.port)).await?;
+    println!(
+        "Serving {} on http://{} ({})",
+        service.root().display(),
+        listener.local_addr()?,
+        serve::ENDPOINTS.join(", ")
+    );
+    if !args.host.is_loopback() {
+        eprintln!("Warning: {} is reachable from other machines", args.host);
+    }
+    serve::serve(Arc::new(service), listener).await?;
+    Ok(())
+}
+
+/// Answers MCP requests on stdin until it's closed
+fn run_mcp(service: ContextService) -> Result<(), Box<dyn Error>> {
+    // Stdout carries the protocol, so anything for people goes to stderr
+    eprintln!("Serving {} over MCP on stdio", service.root().display());
+    McpServer::new(service).run(io::stdin().lock(), io::stdout().lock())?;
+    Ok(())
+}
+
+/// Deletes the caches a run in the directory could use
+fn run_cache(args: &CacheArgs) -> Result<(), Box<dyn Error>> {
+    match &args.command {
+        CacheCommand::Clear { directory } => {
+            let mut cleared = false;
+            let user_dir = Cache::user_dir(directory);
+            for dir in std::iter::once(Cache::project_dir(directory)).chain(user_dir) {
+                if Cache::clear(&dir)? {
+                    println!("Removed {}", dir.display());
+                    cleared = true;
+                }
+            }
+            if !cleared {
+                println!("No cache to clear");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Opens the cache for a scan, unless --no-cache was given
+fn open_cache(scan: &ScanArgs) -> Option<Arc<Cache>> {
+    (!scan.no_cache).then(|| Arc::new(Cache::discover(scan.directory())))
+}
+
+/// Saves the cache; failing to is only worth a warning
+fn save_cache(cache: Option<&Cache>) {
+    if let Some(Err(e)) = cache.map(Cache::save) {
+        eprintln!("Warning: failed to save the cache: {}", e);
+    }
+}
+
+/// Reads a file, or stdin for `-`
+fn read_input(input: &Path) -> Result<String, Box<dyn Error>> {
+    if input.as_os_str() == "-" {
+        return Ok(io::read_to_string(io::stdin())?);
+    }
+    std::fs::read_to_string(input).map_err(|e| promptpal::PromptPalError::from_io(input, e).into())
+}
+
+/// Notes where the files that were overwritten were backed up to
+fn print_backup_note(backups: &[PathBuf], backup_dir: &Path) {
+    if !backups.is_empty() {
+        println!(
+            "Backed up {} overwritten file(s) to {}",
+            backups.len(),
+            backup_dir.display()
+        );
+    }
+}
+
+/// Notes how many sensitive files were left out of the scan
+fn print_sensitive_note(sensitive: &[PathBuf]) {
+    if !sensitive.is_empty() {
+        println!(
+            "\nSkipped {} sensitive file(s) (use --allow-sensitive to include them)",
+            sensitive.len()
+        );
+    }
+}
+
+/// Prints non-fatal warnings to stderr
+fn print_warnings(warnings: &[Warning]) {
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+/// Loads the configuration for a scan, unless --no-config was given
+fn load_settings(scan: &ScanArgs, debug: u8) -> Result<Option<Settings>, Box<dyn Error>> {
+    if scan.no_config {
+        return Ok(None);
+    }
+    let config = Config::discover(scan.directory())?;
+    if debug > 0 {
+        for source in config.sources() {
+            println!("Using configuration from {}", source.display());
+        }
+    }
+    Ok(Some(config.settings(scan.profile.as_deref())?))
+}
+
+fn from_cli(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+/// Resolves a flag and its negation, such as `--line-numbers` and `--no-line-numbers`
+///
+/// Whichever of the two comes last on the command line wins; otherwise the setting does.
+fn flag(matches: &ArgMatches, (on, off): (&str, &str), value: bool, setting: Option<bool>) -> bool {
+    if from_cli(matches, on) || from_cli(matches, off) {
+        value
+    } else {
+        setting.unwrap_or(value)
+    }
+}
+
+/// Fills in scan flags not given on the command line from the configuration
+fn apply_scan_settings(scan: &mut ScanArgs, settings: &Settings, matches: &ArgMatches) {
+    if scan.include.is_empty() {
+        scan.include = settings.include.clone();
+    }
+    if scan.exclude.is_empty() {
+        scan.exclude = settings.exclude.clone();
+    }
+    if let Some(encoding) = settings.encoding.filter(|_| !from_cli(matches, "encoding")) {
+        scan.encoding = encoding;
+    }
+    scan.allow_sensitive = flag(
+        matches,
+        ("allow_sensitive", "no_allow_sensitive"),
+        scan.allow_sensitive,
+        settings.allow_sensitive,
+    );
+    scan.no_cache = flag(
+        matches,
+        ("no_cache", "cache"),
+        scan.no_cache,
+        settings.cache.map(|cache| !cache),
+    );
+}
+
+/// Fills in pack flags not given on the command line from the configuration
+fn apply_pack_settings(
+    args: &mut PackArgs,
+    settings: &Settings,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    if args.strip.is_empty() {
+        args.strip = settings.strip.clone();
+    }
+    if args.outline.is_empty() {
+        args.outline = settings.outline.clone();
+    }
+    if let Some(format) = settings.format.filter(|_| !from_cli(matches, "format")) {
+        args.format = format;
+    }
+    args.model = args.model.take().or(settings.model.clone());
+    args.budget = args.budget.or(settings.budget);
+    args.redactions = args.redactions.take().or(settings.redactions.clone());
+    args.line_numbers = flag(
+        matches,
+        ("line_numbers", "no_line_numbers"),
+        args.line_numbers,
+        settings.line_numbers,
+    );
+    args.no_redact = flag(
+        matches,
+        ("no_redact", "redact"),
+        args.no_redact,
+        settings.redact.map(|redact| !redact),
+    );
+    args.fail_on_secrets = flag(
+        matches,
+        ("fail_on_secrets", "no_fail_on_secrets"),
+        args.fail_on_secrets,
+        settings.fail_on_secrets.map(|fail| fail && !args.no_redact),
+    );
+    if args.instructions.is_none() && args.instructions_file.is_none() && args.task.is_none() {
+        args.instructions = settings.instructions()?;
+    }
+    Ok(())
+}
+
+/// Reads the instructions from stdin, `--instructions-file` or the `--task` template
+fn resolve_instructions(args: &mut PackArgs, prompts: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let stdin = Path::new("-");
+    let file = match (&args.instructions, &args.instructions_file) {
+        (Some(text), _) if text == "-" => Some(stdin),
+        (_, Some(path)) => Some(path.as_path()),
+        _ => None,
+    };
+    if let Some(path) = file {
+        args.instructions = Some(read_input(path)?.trim_end().to_string());
+    } else if let Some(task) = &args.task {
+        let library = PromptLibrary::discover(args.scan.directory(), prompts);
+        let vars: BTreeMap<_, _> = args.vars.iter().cloned().collect();
+        args.instructions = Some(library.load(task)?.render(&vars)?);
+    }
+    Ok(())
+}
+
+/// Configures a prompt builder from the command line flags
+fn pack_builder(
+    cli: &PackArgs,
+    cache: Option<&Arc<Cache>>,
+) -> Result<PromptBuilder, Box<dyn Error>> {
+    let directory = cli.scan.directory();
+    let mut builder = scan_builder(&cli.scan, cache)
+        .format(cli.format)
+        .instructions(cli.instructions.as_deref().unwrap_or(""))
+        .annotate_tree(cli.annotate_tree)
+        .redact(!cli.no_redact)
+        .fail_on_secrets(cli.fail_on_secrets)
+        .rank_by_relevance(cli.rank)
+        .dependencies(cli.dependencies)
+        .outline_fallback(cli.outline_fallback)
+        .line_numbers(cli.line_numbers);
+    for pattern in &cli.outline {
+        builder = builder.outline(pattern);
+    }
+    for strip in &cli.strip {
+        builder = builder.strip(*strip);
+    }
+    if let Some(budget) = cli.budget {
+        builder = builder.budget(budget).fail_on_budget(cli.fail_on_budget);
+    }
+    if let Some(model) = &cli.model {
+        builder = builder.model(model);
+    }
+    if let Some(k) = cli.top_k {
+        builder = builder.top_k(k);
+    }
+    for entry in &cli.follow_imports {
+        builder = builder.follow_imports(entry);
+    }
+    if let Some(depth) = cli.depth {
+        builder = builder.import_depth(depth);
+    }
+    for spec in &cli.files {
+        builder = builder.file(spec.clone());
+    }
+    if let Some(list) = &cli.files_from {
+        for spec in FileSpec::read_list(list)? {
+            builder = builder.file(spec);
         }
     }
 
+    if let Some(path) = rules_file(directory, cli.redactions.as_deref()) {
+        // The rules name what they hide, so they must not end up in the prompt themselves
+        builder = builder
+            .redaction_rules(RedactionRules::load(&path)?)
+            .exclude_file(&path);
+    }
+
+    Ok(builder)
+}
+
+/// Configures a prompt builder with the files a scan selects
+fn scan_builder(scan: &ScanArgs, cache: Option<&Arc<Cache>>) -> PromptBuilder {
+    let mut builder = PromptBuilder::new()
+        .root(scan.directory())
+        .tokenizer(scan.encoding)
+        .allow_sensitive(scan.allow_sensitive);
+    for pattern in &scan.include {
+        builder = builder.include(pattern);
+    }
+    for pattern in &scan.exclude {
+        builder = builder.exclude(pattern);
+    }
+    if let Some(cache) = cache {
+        builder = builder.cache(cache.clone());
+    }
+    builder
+}
+
+/// Returns the redaction rules file to load: the given one, which must exist, or
+/// `redactions.toml` in the directory if there is one
+fn rules_file(directory: &Path, explicit: Option<&Path>) -> Option<PathBuf> {
+    let default_rules = directory.join("redactions.toml");
+    match explicit {
+        Some(path) => Some(path.to_path_buf()),
+        None => default_rules.is_file().then_some(default_rules),
+    }
+}
+
+/// Prints every match the redaction rules would replace without rendering a prompt
+fn run_redact_dry_run(cli: &PackArgs) -> Result<(), Box<dyn Error>> {
+    let matches = pack_builder(cli, None)?.redaction_matches()?;
+    for redaction in &matches {
+        println!(
+            "{}:{}: {}: {}",
+            redaction.path.display(),
+            redaction.line,
+            redaction.kind,
+            redaction.matched
+        );
+    }
+    println!("\n{} match(es) would be redacted", matches.len());
+    Ok(())
+}
+
+/// Renders the prompt for a directory through the library builder
+fn run_pack(cli: &PackArgs) -> Result<(), Box<dyn Error>> {
+    let cache = open_cache(&cli.scan);
+    let builder = pack_builder(cli, cache.as_ref())?;
+
+    // Stream the prompt so large trees are never held in memory at once
+    let label = format_label(cli.format);
+    let report = if let Some(output_path) = &cli.output {
+        // The file is created before the scan, which would otherwise pick it up
+        let builder = builder.exclude_file(output_path);
+        let report = builder.write_to(BufWriter::new(File::create(output_path)?))?;
+        println!("{} output written to: {}", label, output_path.display());
+        report
+    } else {
+        println!("{} output:", label);
+        let report = builder.write_to(io::stdout().lock())?;
+        println!();
+        report
+    };
+    save_cache(cache.as_deref());
+    print_pack_report(cli, &report);
+    Ok(())
+}
+
+/// Renders the prompt to the output file, then again whenever a scanned file changes
+fn run_watch(cli: &PackArgs) -> Result<(), Box<dyn Error>> {
+    let output = cli.output.as_deref().expect("--watch requires --output");
+    let cache = open_cache(&cli.scan);
+    let mut watcher = PromptWatcher::new(pack_builder(cli, cache.as_ref())?, output)?;
+    let rebuild = watcher.build()?;
+    save_cache(cache.as_deref());
+    println!(
+        "{} output written to: {}",
+        format_label(cli.format),
+        output.display()
+    );
+    print_pack_report(cli, &rebuild.report);
+    println!(
+        "\nWatching {} for changes (Ctrl-C to stop)",
+        cli.scan.directory().display()
+    );
+
+    loop {
+        let changed = watcher.wait()?;
+        let time = chrono::Local::now().format("%H:%M:%S");
+        let rebuild = match watcher.build() {
+            Ok(rebuild) => {
+                save_cache(cache.as_deref());
+                rebuild
+            }
+            Err(e) => {
+                eprintln!("\n[{}] Error: {} (the output was left as it was)", time, e);
+                continue;
+            }
+        };
+        println!(
+            "\n[{}] {} changed file(s), rewrote {}: ~{} tokens ({:+})",
+            time,
+            changed.len(),
+            output.display(),
+            rebuild.report.tokens.total_tokens,
+            rebuild.delta()
+        );
+        for change in &rebuild.changes {
+            let path = change.path.display();
+            match (change.before, change.after) {
+                (Some(before), Some(after)) => {
+                    println!(
+                        "  {}: ~{} -> ~{} ({:+})",
+                        path,
+                        before,
+                        after,
+                        change.delta()
+                    )
+                }
+                (None, Some(after)) => println!("  {}: added, ~{} tokens", path, after),
+                _ => println!("  {}: removed ({:+})", path, change.delta()),
+            }
+        }
+        print_warnings(&rebuild.report.warnings);
+    }
+}
+
+fn format_label(format: OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Xml => "XML",
+        OutputFormat::Markdown => "Markdown",
+    }
+}
+
+/// Prints what went into a rendered prompt and its total token count
+fn print_pack_report(cli: &PackArgs, report: &PromptReport) {
+    if cli.per_file {
+        for (i, file) in report.files.iter().enumerate() {
+            match report.relevance.get(i) {
+                Some(relevance) => println!(
+                    "File: {}, Token count: ~{}, Relevance: {:.2}",
+                    file.path.display(),
+                    file.tokens,
+                    relevance.score
+                ),
+                None => println!(
+                    "File: {}, Token count: ~{}",
+                    file.path.display(),
+                    file.tokens
+                ),
+            }
+        }
+    }
+    for file in &report.skipped {
+        println!(
+            "Skipped (over budget or below --top-k): {}, Token count: ~{}",
+            file.path.display(),
+            file.tokens
+        );
+    }
+    for saving in &report.savings {
+        println!(
+            "Stripped: {}, saved ~{} tokens ({} -> {})",
+            saving.path.display(),
+            saving.saved(),
+            saving.before,
+            saving.after
+        );
+    }
+    for path in &report.outlined {
+        println!("Outlined (signatures only): {}", path.display());
+    }
+    print_warnings(&report.warnings);
+    print_sensitive_note(&report.sensitive);
+
+    if !report.redactions.is_empty() {
+        println!("\nRedacted {} secret(s):", report.redactions.len());
+        for redaction in &report.redactions {
+            println!(
+                "  {}:{} ({})",
+                redaction.path.display(),
+                redaction.line,
+                redaction.kind
+            );
+        }
+    }
+
+    // Always display total token count
+    println!(
+        "\nApproximate total tokens across all files: ~{}",
+        report.tokens.total_tokens
+    );
+}
+
+/// Counts tokens for every file and prints the breakdown report
+fn run_report(args: &ReportArgs, redactions: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    // Report on the files a prompt would take, leaving out unreadable ones as it does
+    let directory = args.scan.directory();
+    let cache = open_cache(&args.scan);
+    let mut builder = scan_builder(&args.scan, cache.as_ref());
+    if let Some(path) = rules_file(directory, redactions) {
+        builder = builder.exclude_file(path);
+    }
+    let (files, warnings) = builder.candidates_with_warnings()?;
+    save_cache(cache.as_deref());
+    print_warnings(&warnings);
+
+    let report = TokenReport::build(
+        &[directory.to_path_buf()],
+        &files,
+        context_size(args.model.as_deref()),
+        args.top,
+    );
+    let rendered = report.render(args.format);
+
+    if let Some(output_path) = &args.output {
+        std::fs::write(output_path, rendered)?;
+        println!("Report written to: {}", output_path.display());
+    } else {
+        println!("{}", rendered);
+    }
+
     Ok(())
 }
@@ -0,0 +1,143 @@
+use crate::fileinfo::{ContentMode, FileInfo};
+use crate::render::RenderOptions;
+use crate::tree::FileNode;
+use std::io::{self, Write};
+
+pub struct MarkdownGenerator;
+
+impl MarkdownGenerator {
+    pub fn generate(files: &[FileInfo], instructions: &str) -> String {
+        Self::generate_with_options(files, instructions, &RenderOptions::default())
+    }
+
+    pub fn generate_with_options(
+        files: &[FileInfo],
+        instructions: &str,
+        options: &RenderOptions,
+    ) -> String {
+        let mut md = Vec::new();
+        Self::write(&mut md, files, instructions, options)
+            .expect("Failed to generate Markdown from loaded content");
+        String::from_utf8(md).expect("Generated Markdown is valid UTF-8")
+    }
+
+    /// Writes the Markdown document incrementally to `writer`, loading content
+    /// just before each file is written when `options.lazy_content` is set
+    pub fn write<W: Write>(
+        writer: &mut W,
+        files: &[FileInfo],
+        instructions: &str,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        let root = FileNode::build(files, options);
+
+        if !instructions.is_empty() {
+            write!(writer, "# Instructions\n\n{}\n\n", instructions)?;
+        }
+
+        writer.write_all(b"# Tree\n\n```text\n")?;
+        writer.write_all(root.tree_view(options.annotate_tree).as_bytes())?;
+        writer.write_all(b"```\n\n")?;
+
+        if let Some(graph) = &options.dependencies {
+            writer.write_all(b"# Dependencies\n\n")?;
+            for file in &graph.files {
+                write!(writer, "- `{}`", options.display_path(file).display())?;
+                let imports = graph.edges.get(file).map(Vec::as_slice).unwrap_or_default();
+                for (i, import) in imports.iter().enumerate() {
+                    let separator = if i == 0 { " → " } else { ", " };
+                    write!(
+                        writer,
+                        "{}`{}`",
+                        separator,
+                        options.display_path(import).display()
+                    )?;
+                }
+                writer.write_all(b"\n")?;
+            }
+            writer.write_all(b"\n")?;
+        }
+
+        writer.write_all(b"# Files\n")?;
+
+        Self::write_markdown_from_tree(&root, writer, options)?;
+        writer.flush()
+    }
+
+    fn write_markdown_from_tree<W: Write>(
+        node: &FileNode,
+        writer: &mut W,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        if let Some(file_info) = node.file_info {
+            let mode = match file_info.mode {
+                ContentMode::Full => "",
+                ContentMode::Outline => ", outline",
+            };
+            let range = file_info
+                .range
+                .map(|range| format!(", lines {}", range))
+                .unwrap_or_default();
+            write!(
+                writer,
+                "\n## {} ({} tokens, {} bytes{}{})\n\n",
+                options.display_path(&file_info.path).display(),
+                node.tokens,
+                file_info.size,
+                range,
+                mode
+            )?;
+
+            let file_info = options.load_content(file_info)?;
+            if let Some(content) = &file_info.content {
+                let fence = Self::fence_for(content);
+                writeln!(writer, "{}{}", fence, file_info.file_type())?;
+                writer.write_all(content.as_bytes())?;
+                if !content.ends_with('\n') {
+                    writer.write_all(b"\n")?;
+                }
+                writeln!(writer, "{}", fence)?;
+                if !file_info.is_content_complete {
+                    writer.write_all(b"\n*(preview, content truncated)*\n")?;
+                }
+            }
+        }
+
+        for child in node.sorted_children() {
+            Self::write_markdown_from_tree(child, writer, options)?;
+        }
+        Ok(())
+    }
+
+    /// Picks a backtick fence longer than any backtick run inside the content
+    fn fence_for(content: &str) -> String {
+        let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+        "`".repeat(longest_run.max(2) + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_generate_markdown() {
+        let files = vec![FileInfo {
+            path: PathBuf::from("README.md"),
+            size: 14,
+            content: Some("```sh\nls\n```".to_string()),
+            is_content_complete: true,
+            tokens: Some(7),
+            mode: ContentMode::Full,
+            start_line: 1,
+            range: None,
+        }];
+
+        let md = MarkdownGenerator::generate(&files, "Review this");
+        assert!(md.starts_with("# Instructions\n\nReview this\n"));
+        assert!(md.contains("└── README.md\n"));
+        assert!(md.contains("## README.md (7 tokens, 14 bytes)"));
+        assert!(md.contains("````md\n```sh\nls\n```\n````\n"));
+    }
+}
@@ -0,0 +1,607 @@
+use crate::fileinfo::detect_file_type;
+use regex::Regex;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Languages an outline can be extracted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Language {
+    Rust,
+    Go,
+    JavaScript,
+    Python,
+}
+
+impl Language {
+    fn detect(path: &Path) -> Option<Self> {
+        match detect_file_type(path).as_str() {
+            "rs" => Some(Language::Rust),
+            "go" => Some(Language::Go),
+            "js" | "jsx" | "ts" | "tsx" | "mjs" | "cjs" | "mts" | "cts" => {
+                Some(Language::JavaScript)
+            }
+            "py" | "pyi" => Some(Language::Python),
+            _ => None,
+        }
+    }
+}
+
+/// Returns true if an outline can be extracted from files like `path`
+pub fn is_supported(path: &Path) -> bool {
+    Language::detect(path).is_some()
+}
+
+/// Extracts the outline of a source file: top-level items with their signatures and
+/// doc comments, with function bodies replaced by `...`
+///
+/// Type definitions (structs, enums, interfaces) are kept whole, and the members of
+/// containers (impl blocks, traits, classes) are outlined in turn.
+///
+/// # Returns
+/// * `None` if the file's language isn't supported
+pub fn outline(path: &Path, content: &str) -> Option<String> {
+    outline_lines(path, content).map(|(outline, _)| outline)
+}
+
+/// Like [`outline`], but also returns the 0-based line of `content` each outline line
+/// came from, or `None` for lines standing in for elided bodies
+pub fn outline_lines(path: &Path, content: &str) -> Option<(String, Vec<Option<usize>>)> {
+    let mut out = Lines::default();
+    match Language::detect(path)? {
+        Language::Python => outline_python(content, &mut out),
+        language => outline_braces(language, content, &mut out),
+    }
+    Some((out.text, out.sources))
+}
+
+/// Outline text being built, with the source line of each line
+#[derive(Debug, Default)]
+struct Lines {
+    text: String,
+    sources: Vec<Option<usize>>,
+}
+
+impl Lines {
+    fn push(&mut self, line: &str, source: Option<usize>) {
+        self.text.push_str(line);
+        self.text.push('\n');
+        self.sources.push(source);
+    }
+}
+
+/// How the body of a `{ ... }` block is rendered in an outline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    /// Members are outlined in turn (impl blocks, traits, classes)
+    Container,
+    /// Kept verbatim (type definitions)
+    Keep,
+    /// Replaced by `...` (function bodies and anything else)
+    Elide,
+}
+
+fn word_regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("outline patterns are valid"))
+}
+
+/// Decides how to render a block from the text leading up to its opening brace
+fn classify(language: Language, header: &str) -> Block {
+    static RUST_FN: OnceLock<Regex> = OnceLock::new();
+    static RUST_TYPE: OnceLock<Regex> = OnceLock::new();
+    static RUST_CONTAINER: OnceLock<Regex> = OnceLock::new();
+    static GO_FUNC: OnceLock<Regex> = OnceLock::new();
+    static GO_TYPE: OnceLock<Regex> = OnceLock::new();
+    static JS_FUNCTION: OnceLock<Regex> = OnceLock::new();
+    static JS_TYPE: OnceLock<Regex> = OnceLock::new();
+    static JS_CONTAINER: OnceLock<Regex> = OnceLock::new();
+
+    let matches = |cell, pattern| word_regex(cell, pattern).is_match(header);
+    match language {
+        Language::Rust => {
+            if matches(&RUST_FN, r"\bfn\b") {
+                Block::Elide
+            } else if matches(&RUST_TYPE, r"\b(struct|enum|union)\b") {
+                Block::Keep
+            } else if matches(&RUST_CONTAINER, r"\b(impl|trait|mod|extern)\b") {
+                Block::Container
+            } else {
+                Block::Elide
+            }
+        }
+        Language::Go => {
+            if !matches(&GO_FUNC, r"\bfunc\b") && matches(&GO_TYPE, r"\b(struct|interface)\b") {
+                Block::Keep
+            } else {
+                Block::Elide
+            }
+        }
+        Language::JavaScript => {
+            if matches(&JS_FUNCTION, r"\bfunction\b|=>") {
+                Block::Elide
+            } else if matches(&JS_TYPE, r"\b(interface|enum)\b|\btype\s+\w+.*=") {
+                Block::Keep
+            } else if matches(&JS_CONTAINER, r"\b(class|namespace|module)\b") {
+                Block::Container
+            } else {
+                Block::Elide
+            }
+        }
+        Language::Python => Block::Elide,
+    }
+}
+
+/// Finds the braces in source lines that aren't inside strings or comments
+#[derive(Debug, Default)]
+struct BraceScanner {
+    in_block_comment: bool,
+    /// Closing delimiter of a string continuing from a previous line
+    open_string: Option<String>,
+}
+
+impl BraceScanner {
+    /// Returns the byte offsets and characters of the structural braces in `line`
+    ///
+    /// Works on bytes, since every delimiter it looks for is ASCII.
+    fn scan(&mut self, language: Language, line: &str) -> Vec<(usize, char)> {
+        let bytes = line.as_bytes();
+        let mut braces = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            if self.in_block_comment {
+                match Self::find(bytes, i, b"*/") {
+                    Some(end) => {
+                        self.in_block_comment = false;
+                        i = end;
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+            if let Some(close) = self.open_string.take() {
+                match Self::find_string_end(bytes, i, close.as_bytes()) {
+                    Some(end) => {
+                        i = end;
+                        continue;
+                    }
+                    None => {
+                        self.open_string = Some(close);
+                        break;
+                    }
+                }
+            }
+
+            let rest = &bytes[i..];
+            match bytes[i] {
+                b'/' if rest.starts_with(b"//") => break,
+                b'/' if rest.starts_with(b"/*") => {
+                    self.in_block_comment = true;
+                    i += 2;
+                }
+                b'"' => {
+                    self.open_string = Some("\"".into());
+                    i += 1;
+                }
+                b'`' if language != Language::Rust => {
+                    self.open_string = Some("`".into());
+                    i += 1;
+                }
+                b'\'' if language == Language::JavaScript => {
+                    self.open_string = Some("'".into());
+                    i += 1;
+                }
+                b'\'' => {
+                    // A char or rune literal, or a Rust lifetime
+                    i += Self::char_literal_len(rest).unwrap_or(1);
+                }
+                b'r' if language == Language::Rust
+                    && (i == 0 || !bytes[i - 1].is_ascii_alphanumeric())
+                    && rest[1..]
+                        .iter()
+                        .find(|&&b| b != b'#')
+                        .is_some_and(|&b| b == b'"') =>
+                {
+                    let hashes = rest[1..].iter().take_while(|&&b| b == b'#').count();
+                    self.open_string = Some(format!("\"{}", "#".repeat(hashes)));
+                    i += hashes + 2;
+                }
+                b'{' | b'}' => {
+                    braces.push((i, bytes[i] as char));
+                    i += 1;
+                }
+                _ => i += 1,
+            }
+        }
+        // Only JS template literals and Rust strings may continue on the next line
+        if self.open_string.as_deref() == Some("'") {
+            self.open_string = None;
+        }
+        braces
+    }
+
+    /// Returns the offset just past the first `needle` at or after `from`
+    fn find(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+        bytes
+            .get(from..)?
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .map(|pos| from + pos + needle.len())
+    }
+
+    /// Returns the offset just past the end of a string closed by `close`, starting at `from`
+    fn find_string_end(bytes: &[u8], from: usize, close: &[u8]) -> Option<usize> {
+        let raw = close.len() > 1 || close == b"`";
+        let mut i = from;
+        while i < bytes.len() {
+            if !raw && bytes[i] == b'\\' {
+                i += 2;
+                continue;
+            }
+            if bytes[i..].starts_with(close) {
+                return Some(i + close.len());
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// Returns the length of a char literal such as `'{'` or `'\''` at the start of `bytes`
+    fn char_literal_len(bytes: &[u8]) -> Option<usize> {
+        if *bytes.get(1)? == b'\\' {
+            return Self::find(bytes, 3, b"'");
+        }
+        // Width of the UTF-8 sequence starting at the literal's first character
+        let width = match bytes[1] {
+            b if b < 0x80 => 1,
+            b if b >= 0xf0 => 4,
+            b if b >= 0xe0 => 3,
+            _ => 2,
+        };
+        (bytes.get(1 + width) == Some(&b'\'')).then_some(width + 2)
+    }
+}
+
+/// Outlines a brace-delimited language by eliding the bodies of blocks
+fn outline_braces(language: Language, content: &str, out: &mut Lines) {
+    let mut scanner = BraceScanner::default();
+    let mut stack: Vec<Block> = Vec::new();
+    // Text of the statement being read, used to classify the next block
+    let mut header = String::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let was_eliding = stack.contains(&Block::Elide);
+        let mut cut_at = None;
+        for (pos, brace) in scanner.scan(language, line) {
+            if brace == '}' {
+                stack.pop();
+                continue;
+            }
+            let block = match stack.last() {
+                Some(Block::Elide) => Block::Elide,
+                Some(Block::Keep) => Block::Keep,
+                _ => classify(language, &format!("{}{}", header, &line[..pos])),
+            };
+            if block == Block::Elide && !stack.contains(&Block::Elide) {
+                cut_at = Some(pos);
+            }
+            stack.push(block);
+            header.clear();
+        }
+        let eliding = stack.contains(&Block::Elide);
+
+        if was_eliding {
+            // Inside a body that has already been replaced by `...`
+            continue;
+        }
+        match cut_at {
+            Some(pos) if eliding => {
+                out.push(&format!("{} ... }}", line[..=pos].trim_end()), Some(number));
+            }
+            // One-line bodies are short enough to keep
+            _ => out.push(line, Some(number)),
+        }
+
+        let trimmed = line.trim();
+        let is_comment = ["//", "/*", "*"].iter().any(|c| trimmed.starts_with(c));
+        if trimmed.ends_with([';', '{', '}']) || trimmed.is_empty() {
+            header.clear();
+        } else if cut_at.is_none() && !is_comment {
+            header.push_str(line);
+            header.push(' ');
+        }
+    }
+}
+
+/// Outlines Python by keeping `def`/`class` lines, decorators and docstrings
+fn outline_python(content: &str, out: &mut Lines) {
+    let indent_of = |line: &str| line.len() - line.trim_start().len();
+    let lines: Vec<&str> = content.lines().collect();
+    // Indents of the classes enclosing the current line
+    let mut classes: Vec<usize> = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_start();
+        let indent = indent_of(line);
+        i += 1;
+        if trimmed.is_empty() {
+            continue;
+        }
+        while classes.last().is_some_and(|&class| indent <= class) {
+            classes.pop();
+        }
+        let in_class_body = !classes.is_empty();
+
+        let is_def = trimmed.starts_with("def ") || trimmed.starts_with("async def ");
+        let is_class = trimmed.starts_with("class ");
+        if !is_def && !is_class {
+            // Keep decorators, imports and assignments at module or class level
+            if indent == 0 || in_class_body {
+                out.push(line, Some(i - 1));
+            }
+            continue;
+        }
+
+        // The signature may span lines until its parentheses close
+        out.push(line, Some(i - 1));
+        let mut depth = paren_depth(line);
+        while depth > 0 && i < lines.len() {
+            out.push(lines[i], Some(i));
+            depth += paren_depth(lines[i]);
+            i += 1;
+        }
+
+        // Keep the docstring, if there is one
+        if let Some(next) = lines.get(i) {
+            let doc = next.trim_start();
+            if let Some(quote) = ["\"\"\"", "'''"].into_iter().find(|q| doc.starts_with(q)) {
+                out.push(next, Some(i));
+                i += 1;
+                if !doc[3..].contains(quote) {
+                    while i < lines.len() {
+                        out.push(lines[i], Some(i));
+                        i += 1;
+                        if lines[i - 1].contains(quote) {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if is_class {
+            classes.push(indent);
+            continue;
+        }
+        out.push(&format!("{}...", " ".repeat(indent + 4)), None);
+        // Skip the body: every following line indented deeper than the `def`
+        while i < lines.len() && (lines[i].trim().is_empty() || indent_of(lines[i]) > indent) {
+            i += 1;
+        }
+    }
+}
+
+/// Keywords that introduce a named item in the supported languages
+const DEFINITION_KEYWORDS: &str = "fn|struct|enum|union|trait|impl|mod|type|const|static|macro_rules!|func|var|let|function|class|interface|def";
+
+/// Finds the lines of the item named `symbol`, with its doc comments and attributes
+///
+/// `symbol` is a name such as `parse_header`, optionally after the keyword that
+/// introduces it (`fn parse_header`, `class Server`) to narrow the match. The first
+/// matching definition in the file wins.
+///
+/// # Returns
+/// * 0-based range of lines, or `None` if the language isn't supported or no item has that name
+pub fn find_symbol(path: &Path, content: &str, symbol: &str) -> Option<Range<usize>> {
+    let language = Language::detect(path)?;
+    let (keyword, name) = match symbol.trim().rsplit_once(char::is_whitespace) {
+        Some((keyword, name)) => (regex::escape(keyword.trim()), name),
+        None => (DEFINITION_KEYWORDS.to_string(), symbol.trim()),
+    };
+    // Generics, Go method receivers and `impl Trait for` may sit between keyword and name
+    let definition = Regex::new(&format!(
+        r"(?:^|[^\w$])(?:{})(?:<[^>]*>)?\s+(?:\([^)]*\)\s*)?(?:[\w:<>, ]*\s+for\s+)?{}(?:[^\w$]|$)",
+        keyword,
+        regex::escape(name)
+    ))
+    .ok()?;
+
+    let lines: Vec<&str> = content.lines().collect();
+    let start = lines.iter().position(|line| {
+        let trimmed = line.trim_start();
+        let is_comment = ["//", "/*", "*", "#"]
+            .iter()
+            .any(|c| trimmed.starts_with(c));
+        !is_comment && definition.is_match(line)
+    })?;
+    let end = match language {
+        Language::Python => python_item_end(&lines, start),
+        language => brace_item_end(language, &lines, start),
+    };
+
+    // Pull in the doc comments, attributes and decorators right above the item
+    let preamble: &[&str] = match language {
+        Language::Python => &["@", "#"],
+        _ => &["//", "/*", "*", "#[", "@"],
+    };
+    let mut first = start;
+    while first > 0 {
+        let above = lines[first - 1].trim_start();
+        if !preamble.iter().any(|p| above.starts_with(p)) {
+            break;
+        }
+        first -= 1;
+    }
+    Some(first..end + 1)
+}
+
+/// Returns the last line of the brace-delimited item starting at `start`
+fn brace_item_end(language: Language, lines: &[&str], start: usize) -> usize {
+    let mut scanner = BraceScanner::default();
+    let mut depth = 0;
+    let mut opened = false;
+    for (number, line) in lines.iter().enumerate().skip(start) {
+        for (_, brace) in scanner.scan(language, line) {
+            if brace == '{' {
+                depth += 1;
+                opened = true;
+            } else {
+                depth -= 1;
+            }
+        }
+        if opened && depth <= 0 {
+            return number;
+        }
+        // Items without a body, such as `struct Unit;` or Go's `type ID int`
+        let next_is_blank = lines
+            .get(number + 1)
+            .is_none_or(|next| next.trim().is_empty());
+        if !opened && (line.trim_end().ends_with(';') || next_is_blank) {
+            return number;
+        }
+    }
+    lines.len().saturating_sub(1)
+}
+
+/// Returns the last line of the Python `def` or `class` starting at `start`
+fn python_item_end(lines: &[&str], start: usize) -> usize {
+    let indent_of = |line: &str| line.len() - line.trim_start().len();
+    let indent = indent_of(lines[start]);
+    let mut i = start + 1;
+    let mut depth = paren_depth(lines[start]);
+    while depth > 0 && i < lines.len() {
+        depth += paren_depth(lines[i]);
+        i += 1;
+    }
+    while i < lines.len() && (lines[i].trim().is_empty() || indent_of(lines[i]) > indent) {
+        i += 1;
+    }
+    while i > start + 1 && lines[i - 1].trim().is_empty() {
+        i -= 1;
+    }
+    i - 1
+}
+
+/// Returns the net change in parenthesis depth across a line
+fn paren_depth(line: &str) -> isize {
+    line.chars().fold(0, |depth, c| match c {
+        '(' | '[' => depth + 1,
+        ')' | ']' => depth - 1,
+        _ => depth,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rust_outline() {
+        let source = r#"use std::fmt;
+
+/// A point
+pub struct Point {
+    pub x: i32,
+}
+
+impl Point {
+    /// Creates a point
+    pub fn new(x: i32) -> Self {
+        let brace = '{';
+        Point { x }
+    }
+
+    fn get(&self) -> i32 { self.x }
+}
+
+fn main() {
+    println!("{}", "}");
+}
+"#;
+        let expected = r#"use std::fmt;
+
+/// A point
+pub struct Point {
+    pub x: i32,
+}
+
+impl Point {
+    /// Creates a point
+    pub fn new(x: i32) -> Self { ... }
+
+    fn get(&self) -> i32 { self.x }
+}
+
+fn main() { ... }
+"#;
+        assert_eq!(outline(Path::new("lib.rs"), source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_typescript_and_go_outline() {
+        let source = "export interface Options {\n  depth: number;\n}\n\nexport class Walker {\n  walk(dir: string): string[] {\n    return [`${dir}}`];\n  }\n}\n";
+        assert_eq!(
+            outline(Path::new("walker.ts"), source).unwrap(),
+            "export interface Options {\n  depth: number;\n}\n\nexport class Walker {\n  walk(dir: string): string[] { ... }\n}\n"
+        );
+
+        let source = "// Run starts the server\nfunc Run(addr string) error {\n\treturn nil\n}\n\ntype Server struct {\n\tAddr string\n}\n";
+        assert_eq!(
+            outline(Path::new("server.go"), source).unwrap(),
+            "// Run starts the server\nfunc Run(addr string) error { ... }\n\ntype Server struct {\n\tAddr string\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_python_outline() {
+        let source = r#"import os
+
+class Repo:
+    """A repository"""
+    name = "repo"
+
+    @property
+    def path(self,
+             root):
+        """The path"""
+        return os.path.join(root, self.name)
+
+def main():
+    Repo().path(".")
+"#;
+        let expected = r#"import os
+class Repo:
+    """A repository"""
+    name = "repo"
+    @property
+    def path(self,
+             root):
+        """The path"""
+        ...
+def main():
+    ...
+"#;
+        assert_eq!(outline(Path::new("repo.py"), source).unwrap(), expected);
+        assert!(outline(Path::new("notes.md"), source).is_none());
+    }
+
+    #[test]
+    fn test_find_symbol() {
+        let source = "use std::fmt;\n\n/// A point\n#[derive(Debug)]\npub struct Point {\n    x: i32,\n}\n\nimpl fmt::Display for Point {\n    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {\n        write!(f, \"{}}\", self.x)\n    }\n}\n\nconst ORIGIN: i32 = 0;\n";
+        let path = Path::new("lib.rs");
+        assert_eq!(find_symbol(path, source, "Point"), Some(2..7));
+        assert_eq!(find_symbol(path, source, "impl Point"), Some(8..13));
+        assert_eq!(find_symbol(path, source, "fn fmt"), Some(9..12));
+        assert_eq!(find_symbol(path, source, "ORIGIN"), Some(14..15));
+        assert_eq!(find_symbol(path, source, "Missing"), None);
+
+        let source = "import os\n\n@cache\ndef load(path):\n    return open(path)\n\n\nclass Server:\n    def run(self):\n        pass\n";
+        let path = Path::new("app.py");
+        assert_eq!(find_symbol(path, source, "load"), Some(2..5));
+        assert_eq!(find_symbol(path, source, "class Server"), Some(7..10));
+
+        let source = "// Run starts the server\nfunc (s *Server) Run(addr string) error {\n\treturn nil\n}\n";
+        assert_eq!(find_symbol(Path::new("main.go"), source, "Run"), Some(0..4));
+    }
+}
@@ -0,0 +1,606 @@
+use crate::fileinfo::{ContentMode, FileInfo};
+use crate::render::RenderOptions;
+use crate::report::FileTokens;
+use crate::spec::{FileSpec, Region};
+use crate::tree::FileNode;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Lines above and below the list: key help and search on top, totals at the bottom
+const CHROME_LINES: u16 = 3;
+
+/// A directory or file shown in the picker
+#[derive(Debug, Clone)]
+struct Row {
+    name: String,
+    depth: usize,
+    is_dir: bool,
+    /// Indices into [`Picker::files`] of the files under the row; just one for a file
+    files: Range<usize>,
+}
+
+/// A file that can be picked
+#[derive(Debug, Clone)]
+struct PickFile {
+    /// Path relative to the scanned directory
+    path: PathBuf,
+    tokens: usize,
+}
+
+/// What the picker should do after a key press
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    Confirm,
+    Cancel,
+}
+
+/// State of the interactive file picker, kept apart from the terminal so it can be tested
+#[derive(Debug)]
+pub struct Picker {
+    files: Vec<PickFile>,
+    selected: Vec<bool>,
+    rows: Vec<Row>,
+    /// Rows of collapsed directories
+    collapsed: HashSet<usize>,
+    query: String,
+    /// True while keys go to the search query
+    searching: bool,
+    /// Position of the highlighted row among the visible ones
+    cursor: usize,
+    scroll: usize,
+    budget: usize,
+    /// Regions of files preselected from a file list, kept when the selection is saved
+    regions: HashMap<PathBuf, Region>,
+}
+
+impl Picker {
+    /// Creates a picker over `files`, whose paths are relative to the scanned directory
+    ///
+    /// # Arguments
+    /// * `files` - Files that can be picked, with their token counts
+    /// * `budget` - Tokens the selection is measured against
+    /// * `preselect` - Specs of files to select up front, e.g. from a saved file list;
+    ///   a directory spec selects everything in it
+    pub fn new(files: Vec<FileTokens>, budget: usize, preselect: &[FileSpec]) -> Self {
+        let infos: Vec<FileInfo> = files
+            .into_iter()
+            .map(|file| FileInfo {
+                path: file.path,
+                size: file.size,
+                content: None,
+                is_content_complete: true,
+                tokens: Some(file.tokens),
+                mode: ContentMode::Full,
+                start_line: 1,
+                range: None,
+            })
+            .collect();
+        let tree = FileNode::build(&infos, &RenderOptions::default());
+
+        let mut picker = Picker {
+            files: Vec::new(),
+            selected: Vec::new(),
+            rows: Vec::new(),
+            collapsed: HashSet::new(),
+            query: String::new(),
+            searching: false,
+            cursor: 0,
+            scroll: 0,
+            budget,
+            regions: HashMap::new(),
+        };
+        picker.flatten(&tree, 0);
+        picker.selected = picker
+            .files
+            .iter()
+            .map(|file| {
+                preselect
+                    .iter()
+                    .any(|spec| file.path.starts_with(&spec.path))
+            })
+            .collect();
+        for spec in preselect {
+            if let Some(region) = &spec.region {
+                picker.regions.insert(spec.path.clone(), region.clone());
+            }
+        }
+        picker
+    }
+
+    /// Adds the rows under `node` in tree order
+    fn flatten(&mut self, node: &FileNode, depth: usize) {
+        for child in node.sorted_children() {
+            let row = self.rows.len();
+            let start = self.files.len();
+            self.rows.push(Row {
+                name: child.name.clone(),
+                depth,
+                is_dir: child.file_info.is_none(),
+                files: start..start,
+            });
+            match child.file_info {
+                Some(info) => self.files.push(PickFile {
+                    path: info.path.clone(),
+                    tokens: child.tokens,
+                }),
+                None => self.flatten(child, depth + 1),
+            }
+            self.rows[row].files.end = self.files.len();
+        }
+    }
+
+    /// Rows to show: the tree without collapsed directories, or the files matching
+    /// the search query, best match first
+    fn visible(&self) -> Vec<usize> {
+        if !self.query.is_empty() {
+            let mut matches: Vec<_> = self
+                .rows
+                .iter()
+                .enumerate()
+                .filter(|(_, row)| !row.is_dir)
+                .filter_map(|(index, row)| {
+                    let path = self.files[row.files.start].path.to_string_lossy();
+                    fuzzy_score(&self.query, &path).map(|score| (score, index))
+                })
+                .collect();
+            matches.sort_by_key(|&(score, index)| (std::cmp::Reverse(score), index));
+            return matches.into_iter().map(|(_, index)| index).collect();
+        }
+
+        let mut visible = Vec::new();
+        let mut hidden_below = None;
+        for (index, row) in self.rows.iter().enumerate() {
+            if hidden_below.is_some_and(|depth| row.depth > depth) {
+                continue;
+            }
+            hidden_below = None;
+            visible.push(index);
+            if row.is_dir && self.collapsed.contains(&index) {
+                hidden_below = Some(row.depth);
+            }
+        }
+        visible
+    }
+
+    /// Number of selected files and their total tokens
+    pub fn selection_totals(&self) -> (usize, usize) {
+        self.files
+            .iter()
+            .zip(&self.selected)
+            .filter(|(_, selected)| **selected)
+            .fold((0, 0), |(count, tokens), (file, _)| {
+                (count + 1, tokens + file.tokens)
+            })
+    }
+
+    /// The selected files as specs, in tree order, ready to save as a file list
+    pub fn specs(&self) -> Vec<FileSpec> {
+        self.files
+            .iter()
+            .zip(&self.selected)
+            .filter(|(_, selected)| **selected)
+            .map(|(file, _)| FileSpec {
+                path: file.path.clone(),
+                region: self.regions.get(&file.path).cloned(),
+            })
+            .collect()
+    }
+
+    /// Updates the state for a key press
+    ///
+    /// # Arguments
+    /// * `page` - Rows moved by Page Up and Page Down
+    pub fn handle_key(&mut self, key: KeyEvent, page: usize) -> Action {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Cancel;
+        }
+        if self.searching {
+            match key.code {
+                KeyCode::Esc => {
+                    self.query.clear();
+                    self.searching = false;
+                    self.cursor = 0;
+                }
+                KeyCode::Enter => self.searching = false,
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.cursor = 0;
+                }
+                KeyCode::Up => self.move_cursor(-1),
+                KeyCode::Down => self.move_cursor(1),
+                KeyCode::Char(c) => {
+                    self.query.push(c);
+                    self.cursor = 0;
+                }
+                _ => {}
+            }
+            return Action::Continue;
+        }
+
+        let page = page.max(1) as isize;
+        match key.code {
+            KeyCode::Enter => return Action::Confirm,
+            KeyCode::Char('q') => return Action::Cancel,
+            KeyCode::Esc if self.query.is_empty() => return Action::Cancel,
+            KeyCode::Esc => {
+                self.query.clear();
+                self.cursor = 0;
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_cursor(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_cursor(1),
+            KeyCode::PageUp => self.move_cursor(-page),
+            KeyCode::PageDown => self.move_cursor(page),
+            KeyCode::Home | KeyCode::Char('g') => self.cursor = 0,
+            KeyCode::End | KeyCode::Char('G') => self.move_cursor(isize::MAX / 2),
+            KeyCode::Char(' ') => self.toggle_current(),
+            KeyCode::Char('a') => self.toggle_visible(),
+            KeyCode::Right | KeyCode::Char('l') => self.fold(false),
+            KeyCode::Left | KeyCode::Char('h') => self.fold(true),
+            KeyCode::Char('/') => self.searching = true,
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        let last = self.visible().len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+    }
+
+    fn current_row(&self) -> Option<usize> {
+        self.visible().get(self.cursor).copied()
+    }
+
+    /// Selects every file under the highlighted row, or deselects them if all are selected
+    fn toggle_current(&mut self) {
+        if let Some(row) = self.current_row() {
+            let files = self.rows[row].files.clone();
+            let select = !self.selected[files.clone()].iter().all(|s| *s);
+            self.selected[files].fill(select);
+        }
+    }
+
+    /// Selects every visible file, or deselects them if all are selected
+    fn toggle_visible(&mut self) {
+        let files: Vec<usize> = self
+            .visible()
+            .into_iter()
+            .flat_map(|row| self.rows[row].files.clone())
+            .collect();
+        let select = !files.iter().all(|&file| self.selected[file]);
+        for file in files {
+            self.selected[file] = select;
+        }
+    }
+
+    /// Collapses or expands the highlighted directory; collapsing a file or a
+    /// collapsed directory moves to its parent
+    fn fold(&mut self, collapse: bool) {
+        let Some(row) = self.current_row().filter(|_| self.query.is_empty()) else {
+            return;
+        };
+        if !collapse {
+            self.collapsed.remove(&row);
+            return;
+        }
+        if self.rows[row].is_dir && self.collapsed.insert(row) {
+            return;
+        }
+        let depth = self.rows[row].depth;
+        if let Some(parent) = (0..row).rev().find(|&i| self.rows[i].depth + 1 == depth) {
+            self.collapsed.insert(parent);
+            if let Some(position) = self.visible().iter().position(|&i| i == parent) {
+                self.cursor = position;
+            }
+        }
+    }
+
+    /// Checkbox for a row: all, some or none of its files selected
+    fn checkbox(&self, row: &Row) -> &'static str {
+        let selected = self.selected[row.files.clone()]
+            .iter()
+            .filter(|s| **s)
+            .count();
+        match selected {
+            0 => "[ ]",
+            n if n == row.files.len() => "[x]",
+            _ => "[~]",
+        }
+    }
+
+    /// Text of a row, without highlighting
+    fn row_text(&self, index: usize, width: usize) -> String {
+        let row = &self.rows[index];
+        let tokens =
+            |files: Range<usize>| -> usize { self.files[files].iter().map(|f| f.tokens).sum() };
+        let (label, count) = if !self.query.is_empty() {
+            let file = &self.files[row.files.start];
+            (file.path.display().to_string(), file.tokens.to_string())
+        } else if row.is_dir {
+            let marker = if self.collapsed.contains(&index) {
+                "▸"
+            } else {
+                "▾"
+            };
+            let selected: usize = row
+                .files
+                .clone()
+                .filter(|&file| self.selected[file])
+                .map(|file| self.files[file].tokens)
+                .sum();
+            (
+                format!("{}{} {}/", "  ".repeat(row.depth), marker, row.name),
+                format!("{}/{}", selected, tokens(row.files.clone())),
+            )
+        } else {
+            (
+                format!("{}  {}", "  ".repeat(row.depth), row.name),
+                tokens(row.files.clone()).to_string(),
+            )
+        };
+        let left = format!(" {} {}", self.checkbox(row), label);
+        let right = format!("{} ", count);
+        let (left_len, right_len) = (left.chars().count(), right.chars().count());
+        if left_len + right_len + 1 > width {
+            return left.chars().take(width).collect();
+        }
+        format!(
+            "{}{}{}",
+            left,
+            " ".repeat(width - left_len - right_len),
+            right
+        )
+    }
+
+    /// Draws the picker on the whole screen
+    fn draw<W: Write>(&mut self, out: &mut W, width: u16, height: u16) -> io::Result<()> {
+        // Leave the last column free so full rows never wrap
+        let width = (width as usize).saturating_sub(1);
+        let view = height.saturating_sub(CHROME_LINES).max(1) as usize;
+        let visible = self.visible();
+        self.cursor = self.cursor.min(visible.len().saturating_sub(1));
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + view {
+            self.scroll = self.cursor + 1 - view;
+        }
+
+        let help = " ↑↓ move  space toggle  ←→ fold  / search  a all  enter confirm  q quit";
+        queue!(
+            out,
+            cursor::MoveTo(0, 0),
+            SetAttribute(Attribute::Dim),
+            Print(help.chars().take(width).collect::<String>()),
+            SetAttribute(Attribute::Reset),
+            Clear(ClearType::UntilNewLine),
+            cursor::MoveTo(0, 1),
+        )?;
+        if self.searching || !self.query.is_empty() {
+            let caret = if self.searching { "_" } else { "" };
+            let search = format!(" Search: {}{}", self.query, caret);
+            queue!(out, Print(search.chars().take(width).collect::<String>()))?;
+        }
+        // Redraw line by line rather than clearing the screen, so it doesn't flicker
+        queue!(out, Clear(ClearType::UntilNewLine))?;
+
+        for (line, &row) in visible.iter().skip(self.scroll).take(view).enumerate() {
+            queue!(out, cursor::MoveTo(0, line as u16 + 2))?;
+            let text = self.row_text(row, width);
+            if self.scroll + line == self.cursor {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(text),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(text))?;
+            }
+            queue!(out, Clear(ClearType::UntilNewLine))?;
+        }
+        let drawn = visible.len().saturating_sub(self.scroll).min(view);
+        queue!(
+            out,
+            cursor::MoveTo(0, drawn as u16 + 2),
+            Clear(ClearType::FromCursorDown)
+        )?;
+        if visible.is_empty() {
+            queue!(out, Print(" No matching files"))?;
+        }
+
+        let (count, tokens) = self.selection_totals();
+        let percent = tokens as f64 * 100.0 / self.budget.max(1) as f64;
+        let footer = format!(
+            " {} of {} files selected, {} tokens of {} ({:.1}%)",
+            count,
+            self.files.len(),
+            tokens,
+            self.budget,
+            percent
+        );
+        queue!(out, cursor::MoveTo(0, height.saturating_sub(1)))?;
+        if tokens > self.budget {
+            queue!(out, SetForegroundColor(Color::Red))?;
+        }
+        queue!(
+            out,
+            SetAttribute(Attribute::Bold),
+            Print(footer.chars().take(width).collect::<String>()),
+            SetAttribute(Attribute::Reset),
+            ResetColor
+        )?;
+        out.flush()
+    }
+}
+
+/// Runs the picker in the terminal, drawing on stderr so stdout stays free for the prompt
+///
+/// # Returns
+/// * The selected files when confirmed with Enter, or `None` when cancelled
+pub fn run(mut picker: Picker) -> io::Result<Option<Vec<FileSpec>>> {
+    let mut out = io::stderr();
+    terminal::enable_raw_mode()?;
+    let result = execute!(out, EnterAlternateScreen, cursor::Hide)
+        .and_then(|_| event_loop(&mut picker, &mut out));
+    // Restore the terminal even if drawing failed
+    let _ = execute!(out, cursor::Show, LeaveAlternateScreen);
+    terminal::disable_raw_mode()?;
+    Ok(result?.then(|| picker.specs()))
+}
+
+fn event_loop<W: Write>(picker: &mut Picker, out: &mut W) -> io::Result<bool> {
+    loop {
+        let (width, height) = terminal::size()?;
+        picker.draw(out, width, height)?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let page = height.saturating_sub(CHROME_LINES) as usize;
+        match picker.handle_key(key, page) {
+            Action::Continue => {}
+            Action::Confirm => return Ok(true),
+            Action::Cancel => return Ok(false),
+        }
+    }
+}
+
+/// Scores how well `query` matches `candidate` as a case-insensitive subsequence
+///
+/// Consecutive characters, matches at the start of a path component or word, and
+/// matches within the file name score higher; gaps score lower. Returns `None` if
+/// some character of the query doesn't appear in order.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let chars: Vec<char> = candidate.chars().collect();
+    let name_start = chars.iter().rposition(|&c| c == '/').map_or(0, |i| i + 1);
+
+    let mut score = 0;
+    let mut wanted = query.iter().peekable();
+    let mut previous: Option<usize> = None;
+    for (i, &c) in chars.iter().enumerate() {
+        let Some(&&next) = wanted.peek() else {
+            break;
+        };
+        if !c.to_lowercase().eq(std::iter::once(next)) {
+            continue;
+        }
+        wanted.next();
+        score += 1;
+        match previous {
+            Some(p) if p + 1 == i => score += 5,
+            Some(p) => score -= (i - p - 1).min(5) as i64,
+            None => {}
+        }
+        let at_boundary = i == 0 || matches!(chars[i - 1], '/' | '_' | '-' | '.' | ' ');
+        let camel = i > 0 && c.is_uppercase() && chars[i - 1].is_lowercase();
+        if at_boundary || camel {
+            score += 8;
+        }
+        if i >= name_start {
+            score += 2;
+        }
+        previous = Some(i);
+    }
+    wanted.peek().is_none().then_some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picker(preselect: &[FileSpec]) -> Picker {
+        let file = |path: &str, tokens| FileTokens {
+            path: PathBuf::from(path),
+            size: 0,
+            tokens,
+        };
+        Picker::new(
+            vec![
+                file("src/main.rs", 10),
+                file("README.md", 5),
+                file("src/cli/args.rs", 20),
+            ],
+            100,
+            preselect,
+        )
+    }
+
+    fn press(picker: &mut Picker, code: KeyCode) -> Action {
+        picker.handle_key(KeyEvent::new(code, KeyModifiers::NONE), 10)
+    }
+
+    #[test]
+    fn test_tree_rows_and_toggling() {
+        let mut picker = picker(&[]);
+        let names: Vec<_> = picker.rows.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["README.md", "src", "cli", "args.rs", "main.rs"]);
+
+        // Toggling a directory selects everything under it
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Char(' '));
+        assert_eq!(picker.selection_totals(), (2, 30));
+        assert_eq!(picker.checkbox(&picker.rows[1]), "[x]");
+
+        // Deselecting one file leaves the directory partly selected
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Char(' '));
+        assert_eq!(picker.checkbox(&picker.rows[1]), "[~]");
+        assert_eq!(picker.specs(), vec!["src/main.rs".parse().unwrap()]);
+
+        // Collapsing from a file folds its directory and moves to it
+        press(&mut picker, KeyCode::Left);
+        assert_eq!(picker.current_row(), Some(2));
+        assert_eq!(picker.visible(), vec![0, 1, 2, 4]);
+        press(&mut picker, KeyCode::Right);
+        assert_eq!(picker.visible().len(), 5);
+        assert_eq!(press(&mut picker, KeyCode::Enter), Action::Confirm);
+    }
+
+    #[test]
+    fn test_search_and_preselection() {
+        let mut picker = picker(&["src/cli/args.rs:1-5".parse().unwrap()]);
+        assert_eq!(picker.selection_totals(), (1, 20));
+
+        press(&mut picker, KeyCode::Char('/'));
+        for c in "mainrs".chars() {
+            press(&mut picker, KeyCode::Char(c));
+        }
+        assert_eq!(picker.visible(), vec![4]);
+        press(&mut picker, KeyCode::Enter);
+        press(&mut picker, KeyCode::Char(' '));
+        assert_eq!(
+            picker
+                .specs()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["src/cli/args.rs:1-5", "src/main.rs"]
+        );
+        assert_eq!(press(&mut picker, KeyCode::Esc), Action::Continue);
+        assert_eq!(press(&mut picker, KeyCode::Esc), Action::Cancel);
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("xyz", "src/main.rs").is_none());
+        let name = fuzzy_score("main", "src/main.rs").unwrap();
+        let scattered = fuzzy_score("main", "src/mod/ai/n.rs").unwrap();
+        assert!(name > scattered);
+        assert!(fuzzy_score("SMR", "src/main.rs").is_some());
+    }
+}
@@ -0,0 +1,234 @@
+use crate::config::user_config_path;
+use crate::error::{PromptPalError, Result};
+use crate::walkdir::STATE_DIR_NAME;
+use regex::{Captures, Regex};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Extensions a template file may have; a task name can also match a file without one
+const TEMPLATE_EXTENSIONS: &[&str] = &["md", "txt"];
+
+/// Directories of named instruction templates, searched in order
+///
+/// A task named `review` is read from `review.md`, `review.txt` or `review` in the
+/// first directory that has one.
+#[derive(Debug, Clone, Default)]
+pub struct PromptLibrary {
+    dirs: Vec<PathBuf>,
+}
+
+impl PromptLibrary {
+    /// Creates a library searching `dirs` in order
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        PromptLibrary { dirs }
+    }
+
+    /// Searches the configured directory, then `.promptpal/prompts` under `root`, then
+    /// `prompts` in the user configuration directory
+    pub fn discover(root: &Path, configured: Option<&Path>) -> Self {
+        let mut dirs: Vec<PathBuf> = configured.map(Path::to_path_buf).into_iter().collect();
+        dirs.push(root.join(STATE_DIR_NAME).join("prompts"));
+        if let Some(dir) = user_config_path().as_deref().and_then(Path::parent) {
+            dirs.push(dir.join("prompts"));
+        }
+        PromptLibrary::new(dirs)
+    }
+
+    /// Names of the templates across all directories
+    pub fn names(&self) -> BTreeSet<String> {
+        self.dirs
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| {
+                let path = entry.path();
+                let has_template_extension = path
+                    .extension()
+                    .is_none_or(|extension| TEMPLATE_EXTENSIONS.iter().any(|e| extension == *e));
+                has_template_extension
+                    .then(|| path.file_stem()?.to_str().map(str::to_string))
+                    .flatten()
+            })
+            .collect()
+    }
+
+    /// Loads the template for `task`
+    pub fn load(&self, task: &str) -> Result<Template> {
+        let file_names = TEMPLATE_EXTENSIONS
+            .iter()
+            .map(|extension| format!("{}.{}", task, extension))
+            .chain([task.to_string()]);
+        let found = self.dirs.iter().find_map(|dir| {
+            file_names
+                .clone()
+                .map(|name| dir.join(name))
+                .find(|path| path.is_file())
+        });
+        let Some(path) = found else {
+            let names: Vec<_> = self.names().into_iter().collect();
+            return Err(PromptPalError::Template {
+                path: self.dirs.first().cloned().unwrap_or_default(),
+                message: if names.is_empty() {
+                    format!("unknown task '{}' (the prompt library is empty)", task)
+                } else {
+                    format!("unknown task '{}' (available: {})", task, names.join(", "))
+                },
+            });
+        };
+        let text = fs::read_to_string(&path).map_err(|e| PromptPalError::from_io(&path, e))?;
+        Ok(Template {
+            path,
+            text: text.trim_end().to_string(),
+        })
+    }
+}
+
+/// Instructions with `{{name}}` placeholders, optionally with a default: `{{name|default}}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    /// File the template was read from
+    pub path: PathBuf,
+    pub text: String,
+}
+
+impl Template {
+    /// Substitutes `vars` into the template
+    ///
+    /// # Returns
+    /// * A [`Template`](PromptPalError::Template) error naming the variables that have
+    ///   no value or default, or that were given but aren't used by the template
+    pub fn render(&self, vars: &BTreeMap<String, String>) -> Result<String> {
+        let mut missing = BTreeSet::new();
+        let rendered = placeholder().replace_all(&self.text, |captures: &Captures| {
+            let name = &captures[1];
+            match (vars.get(name), captures.get(2)) {
+                (Some(value), _) => value.clone(),
+                (None, Some(default)) => default.as_str().trim().to_string(),
+                (None, None) => {
+                    missing.insert(name.to_string());
+                    String::new()
+                }
+            }
+        });
+
+        let error = |message| PromptPalError::Template {
+            path: self.path.clone(),
+            message,
+        };
+        if !missing.is_empty() {
+            let missing: Vec<_> = missing.into_iter().collect();
+            return Err(error(format!(
+                "no value for {} (pass --var {}=...)",
+                missing.join(", "),
+                missing[0]
+            )));
+        }
+        let used = self.variables();
+        let unused: Vec<_> = vars.keys().filter(|name| !used.contains(*name)).collect();
+        if !unused.is_empty() {
+            let names: Vec<_> = unused.iter().map(|name| name.as_str()).collect();
+            return Err(error(format!(
+                "the template has no variable {}",
+                names.join(", ")
+            )));
+        }
+        Ok(rendered.into_owned())
+    }
+
+    /// Names of the variables the template uses
+    pub fn variables(&self) -> BTreeSet<String> {
+        placeholder()
+            .captures_iter(&self.text)
+            .map(|captures| captures[1].to_string())
+            .collect()
+    }
+}
+
+fn placeholder() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| {
+        Regex::new(r"\{\{\s*([A-Za-z_][\w-]*)\s*(?:\|([^}]*))?\}\}")
+            .expect("Placeholder regex is valid")
+    })
+}
+
+/// Parses a `name=value` template variable
+pub fn parse_var(var: &str) -> std::result::Result<(String, String), String> {
+    let (name, value) = var
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got '{}'", var))?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("missing variable name in '{}'", var));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_render_template() {
+        let template = Template {
+            path: PathBuf::from("review.md"),
+            text: "Review for {{ focus }} issues in {{scope|the whole crate}}.".into(),
+        };
+        let vars = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            template.render(&vars(&[("focus", "security")])).unwrap(),
+            "Review for security issues in the whole crate."
+        );
+        assert_eq!(
+            template
+                .render(&vars(&[("focus", "perf"), ("scope", "src/db")]))
+                .unwrap(),
+            "Review for perf issues in src/db."
+        );
+        let err = template.render(&vars(&[])).unwrap_err().to_string();
+        assert!(err.contains("no value for focus"), "{}", err);
+        let err = template
+            .render(&vars(&[("focus", "x"), ("fcous", "y")]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("no variable fcous"), "{}", err);
+
+        assert_eq!(parse_var("focus=a=b"), Ok(("focus".into(), "a=b".into())));
+        assert!(parse_var("focus").is_err());
+    }
+
+    #[test]
+    fn test_library_lookup() {
+        let dir = tempdir().unwrap();
+        let (project, user) = (dir.path().join("project"), dir.path().join("user"));
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(&user).unwrap();
+        fs::write(project.join("review.md"), "Project review\n").unwrap();
+        fs::write(user.join("review.txt"), "User review").unwrap();
+        fs::write(user.join("explain"), "Explain {{topic}}").unwrap();
+        fs::write(user.join("notes.json"), "{}").unwrap();
+
+        let library = PromptLibrary::new(vec![project.clone(), user]);
+        assert_eq!(library.load("review").unwrap().text, "Project review");
+        assert_eq!(
+            library.load("explain").unwrap().variables(),
+            BTreeSet::from(["topic".to_string()])
+        );
+        assert_eq!(
+            library.names().into_iter().collect::<Vec<_>>(),
+            vec!["explain", "review"]
+        );
+        let err = library.load("missing").unwrap_err().to_string();
+        assert!(err.contains("available: explain, review"), "{}", err);
+    }
+}
@@ -0,0 +1,210 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// BM25 term frequency saturation
+const K1: f64 = 1.2;
+/// BM25 document length normalization
+const B: f64 = 0.75;
+/// How many times each path term is counted, so file names outweigh a passing mention
+const PATH_WEIGHT: usize = 3;
+
+/// Common English words left out of queries
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "how", "in", "is", "it", "of",
+    "on", "or", "that", "the", "this", "to", "was", "what", "when", "where", "which", "with",
+];
+
+/// How relevant a file is to the prompt instructions
+#[derive(Debug, Clone, Serialize)]
+pub struct Relevance {
+    pub path: PathBuf,
+    /// BM25 score; 0 when no query term appears in the file
+    pub score: f64,
+}
+
+/// Term counts for a single file
+#[derive(Debug, Clone, Default)]
+struct Document {
+    terms: HashMap<String, usize>,
+    len: usize,
+}
+
+/// A local BM25 index over file paths, identifiers and contents
+///
+/// Files are scored against a free-text query such as the prompt instructions.
+/// Scoring is fully offline and deterministic: ties keep the order files were added in.
+#[derive(Debug, Clone, Default)]
+pub struct RelevanceIndex {
+    docs: Vec<Document>,
+    /// Number of documents each term appears in
+    doc_freq: HashMap<String, usize>,
+    total_len: usize,
+}
+
+impl RelevanceIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file to the index and returns its position
+    ///
+    /// # Arguments
+    /// * `path` - Path shown for the file, usually relative to its scan root
+    /// * `content` - The file's content
+    pub fn add(&mut self, path: &Path, content: &str) -> usize {
+        let mut doc = Document::default();
+        for term in terms(&path.to_string_lossy()) {
+            *doc.terms.entry(term).or_default() += PATH_WEIGHT;
+            doc.len += PATH_WEIGHT;
+        }
+        for term in terms(content) {
+            *doc.terms.entry(term).or_default() += 1;
+            doc.len += 1;
+        }
+
+        for term in doc.terms.keys() {
+            *self.doc_freq.entry(term.clone()).or_default() += 1;
+        }
+        self.total_len += doc.len;
+        self.docs.push(doc);
+        self.docs.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    /// Returns the BM25 score of every file against `query`, in the order files were added
+    pub fn scores(&self, query: &str) -> Vec<f64> {
+        let mut query_terms = terms(query);
+        query_terms.retain(|term| !STOP_WORDS.contains(&term.as_str()));
+        query_terms.sort();
+        query_terms.dedup();
+
+        let count = self.docs.len() as f64;
+        let avg_len = if self.docs.is_empty() {
+            0.0
+        } else {
+            self.total_len as f64 / count
+        };
+
+        self.docs
+            .iter()
+            .map(|doc| {
+                query_terms
+                    .iter()
+                    .filter_map(|term| {
+                        let tf = *doc.terms.get(term)? as f64;
+                        let df = self.doc_freq[term] as f64;
+                        let idf = ((count - df + 0.5) / (df + 0.5) + 1.0).ln();
+                        let norm = if avg_len > 0.0 {
+                            1.0 - B + B * doc.len as f64 / avg_len
+                        } else {
+                            1.0
+                        };
+                        Some(idf * tf * (K1 + 1.0) / (tf + K1 * norm))
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Returns file positions ordered from most to least relevant to `query`
+    pub fn rank(&self, query: &str) -> Vec<(usize, f64)> {
+        let mut ranked: Vec<_> = self.scores(query).into_iter().enumerate().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked
+    }
+}
+
+/// Splits text into lowercase search terms
+///
+/// Identifiers are kept whole and also split into their `snake_case` and
+/// `camelCase` parts, so `parseConfig` matches queries for "parse" and "config".
+pub fn terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    for word in text
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+    {
+        let parts = split_identifier(word);
+        if parts.len() != 1 {
+            terms.push(word.to_lowercase());
+        }
+        terms.extend(parts);
+    }
+    terms
+}
+
+/// Splits an identifier on underscores and lower-to-upper case changes
+fn split_identifier(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    for chunk in word.split('_').filter(|chunk| !chunk.is_empty()) {
+        let mut current = String::new();
+        let mut prev_lower = false;
+        for c in chunk.chars() {
+            if c.is_uppercase() && prev_lower && !current.is_empty() {
+                parts.push(std::mem::take(&mut current).to_lowercase());
+            }
+            prev_lower = c.is_lowercase() || c.is_ascii_digit();
+            current.push(c);
+        }
+        parts.push(current.to_lowercase());
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terms_split_identifiers() {
+        assert_eq!(
+            terms("fn parseConfig(raw_input: &str)"),
+            vec![
+                "fn",
+                "parseconfig",
+                "parse",
+                "config",
+                "raw_input",
+                "raw",
+                "input",
+                "str"
+            ]
+        );
+        assert_eq!(terms("HTTPServer v2"), vec!["httpserver", "v2"]);
+    }
+
+    #[test]
+    fn test_rank_by_relevance() {
+        let mut index = RelevanceIndex::new();
+        index.add(
+            Path::new("src/render.rs"),
+            "fn render(files: &[FileInfo]) {}",
+        );
+        index.add(
+            Path::new("src/auth/login.rs"),
+            "fn check_password(user: &User) -> bool { verify_token(user) }",
+        );
+        index.add(Path::new("README.md"), "How to log in to the service");
+
+        let ranked = index.rank("Review the login and password handling");
+        assert_eq!(ranked[0].0, 1);
+        assert!(ranked[0].1 > 0.0);
+        // Files with no matching terms keep their original order
+        assert_eq!(
+            ranked[1..].iter().map(|r| r.0).collect::<Vec<_>>(),
+            vec![0, 2]
+        );
+        assert_eq!(ranked[1].1, 0.0);
+
+        // Stop words alone match nothing
+        assert!(index.scores("the and of").iter().all(|score| *score == 0.0));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::fileinfo::FileInfo;
+use crate::imports::ImportGraph;
+use crate::markdown::MarkdownGenerator;
+use crate::xml::XmlGenerator;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Output formats a prompt can be rendered in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    #[default]
+    Xml,
+    Markdown,
+}
+
+/// Rewrites file content and displayed paths between loading and rendering
+pub trait Transform: fmt::Debug + Send + Sync {
+    /// Rewrites a file's content
+    ///
+    /// `file` carries the path, [`ContentMode`](crate::fileinfo::ContentMode) and
+    /// start line the content was selected with.
+    fn content(&self, file: &FileInfo, content: String) -> String;
+
+    /// Rewrites the path shown for a file in the output
+    fn path(&self, path: &Path) -> PathBuf {
+        path.to_path_buf()
+    }
+}
+
+/// Options shared by all renderers
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Annotate tree view lines with token counts and sizes
+    pub annotate_tree: bool,
+    /// Read the full content of files without loaded content just before they're written
+    pub lazy_content: bool,
+    /// Applied to displayed paths, and to lazily loaded content so it matches what
+    /// was counted up front
+    pub transform: Option<Arc<dyn Transform>>,
+    /// Import graph to emit as a dependencies section
+    pub dependencies: Option<ImportGraph>,
+    /// Content is prefixed with line numbers; adds a `start_line` attribute to XML content
+    /// (always added for files limited to a range of lines)
+    pub line_numbers: bool,
+}
+
+impl RenderOptions {
+    /// Returns the path to show for a file in the output
+    pub(crate) fn display_path<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        match &self.transform {
+            Some(transform) => Cow::Owned(transform.path(path)),
+            None => Cow::Borrowed(path),
+        }
+    }
+
+    /// Returns the file with its full content, reading it from disk if lazy loading applies
+    pub(crate) fn load_content<'a>(&self, file: &'a FileInfo) -> io::Result<Cow<'a, FileInfo>> {
+        if !self.lazy_content || file.content.is_some() {
+            return Ok(Cow::Borrowed(file));
+        }
+        let mut loaded = FileInfo::with_full_content(&file.path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file.path.display(), e)))?;
+        loaded.tokens = file.tokens;
+        loaded.mode = file.mode;
+        loaded.start_line = file.start_line;
+        loaded.range = file.range;
+        if let Some(range) = file.range {
+            loaded.content = loaded
+                .content
+                .map(|content| range.slice(&content).to_string());
+        }
+        if let Some(transform) = &self.transform {
+            loaded.content = loaded
+                .content
+                .map(|content| transform.content(file, content));
+        }
+        Ok(Cow::Owned(loaded))
+    }
+}
+
+/// Renders files and instructions in the requested format
+pub fn render(
+    format: OutputFormat,
+    files: &[FileInfo],
+    instructions: &str,
+    options: &RenderOptions,
+) -> String {
+    match format {
+        OutputFormat::Xml => XmlGenerator::generate_with_options(files, instructions, options),
+        OutputFormat::Markdown => {
+            MarkdownGenerator::generate_with_options(files, instructions, options)
+        }
+    }
+}
+
+/// Writes files and instructions in the requested format to `writer`
+pub fn write<W: Write>(
+    writer: &mut W,
+    format: OutputFormat,
+    files: &[FileInfo],
+    instructions: &str,
+    options: &RenderOptions,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Xml => XmlGenerator::write(writer, files, instructions, options),
+        OutputFormat::Markdown => MarkdownGenerator::write(writer, files, instructions, options),
+    }
+}
@@ -0,0 +1,321 @@
+use crate::error::{PromptPalError, Result};
+use crate::fileinfo::{detect_file_type, FileInfo};
+use crate::tokenizer::Encoding;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the directory entry for files directly in a scan root
+const ROOT_ENTRY: &str = "(root)";
+
+/// Output formats supported by the token report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Token count and size of a single file
+#[derive(Debug, Clone, Serialize)]
+pub struct FileTokens {
+    /// Path to the file
+    pub path: PathBuf,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Number of tokens in the file's content
+    pub tokens: usize,
+}
+
+impl FileTokens {
+    /// Reads a file in full and counts its tokens with `encoding`
+    pub fn measure(path: impl AsRef<Path>, encoding: Encoding) -> Result<Self> {
+        let path = path.as_ref();
+        let info =
+            FileInfo::with_full_content(path).map_err(|e| PromptPalError::from_io(path, e))?;
+        let tokens = encoding.count(info.content.as_deref().unwrap_or_default());
+
+        Ok(FileTokens {
+            path: info.path,
+            size: info.size,
+            tokens,
+        })
+    }
+}
+
+/// Aggregated token usage for a directory or a file type
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportEntry {
+    pub name: String,
+    pub files: usize,
+    pub size: u64,
+    pub tokens: usize,
+    pub percent_of_total: f64,
+    pub percent_of_context: f64,
+}
+
+/// Token usage broken down by directory, file type and heaviest files
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenReport {
+    pub total_files: usize,
+    pub total_size: u64,
+    pub total_tokens: usize,
+    pub context_size: usize,
+    pub percent_of_context: f64,
+    /// Directories with rolled-up totals (including subdirectories), heaviest first;
+    /// files directly in a scan root are listed as `(root)`
+    pub directories: Vec<ReportEntry>,
+    /// File types, heaviest first
+    pub file_types: Vec<ReportEntry>,
+    /// The `top_n` heaviest individual files
+    pub top_files: Vec<ReportEntry>,
+}
+
+impl TokenReport {
+    /// Builds a report from per-file token counts
+    ///
+    /// # Arguments
+    /// * `roots` - Scan roots; paths are reported relative to the root they were found under
+    /// * `files` - Token counts for every file in the scan
+    /// * `context_size` - Context window of the model percentages are measured against
+    /// * `top_n` - Number of heaviest files to include
+    pub fn build(
+        roots: &[PathBuf],
+        files: &[FileTokens],
+        context_size: usize,
+        top_n: usize,
+    ) -> Self {
+        let total_tokens: usize = files.iter().map(|f| f.tokens).sum();
+        let total_size: u64 = files.iter().map(|f| f.size).sum();
+        let percent = |tokens: usize, of: usize| {
+            if of == 0 {
+                0.0
+            } else {
+                tokens as f64 * 100.0 / of as f64
+            }
+        };
+
+        let mut directories: HashMap<String, (usize, u64, usize)> = HashMap::new();
+        let mut file_types: HashMap<String, (usize, u64, usize)> = HashMap::new();
+        let mut top_files = Vec::with_capacity(files.len());
+
+        for file in files {
+            let relative = roots
+                .iter()
+                .find_map(|root| file.path.strip_prefix(root).ok())
+                .unwrap_or(&file.path);
+
+            // Roll each file up into every directory above it
+            let mut parents: Vec<_> = relative
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| !dir.as_os_str().is_empty())
+                .map(|dir| format!("{}/", dir.display()))
+                .collect();
+            if parents.is_empty() {
+                parents.push(ROOT_ENTRY.to_string());
+            }
+            for dir in parents {
+                let entry = directories.entry(dir).or_default();
+                entry.0 += 1;
+                entry.1 += file.size;
+                entry.2 += file.tokens;
+            }
+
+            let entry = file_types.entry(detect_file_type(relative)).or_default();
+            entry.0 += 1;
+            entry.1 += file.size;
+            entry.2 += file.tokens;
+
+            top_files.push((relative.display().to_string(), (1, file.size, file.tokens)));
+        }
+
+        let to_entries = |groups: Vec<(String, (usize, u64, usize))>| {
+            let mut entries: Vec<_> = groups
+                .into_iter()
+                .map(|(name, (files, size, tokens))| ReportEntry {
+                    name,
+                    files,
+                    size,
+                    tokens,
+                    percent_of_total: percent(tokens, total_tokens),
+                    percent_of_context: percent(tokens, context_size),
+                })
+                .collect();
+            entries.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.name.cmp(&b.name)));
+            entries
+        };
+
+        let mut top_files = to_entries(top_files);
+        top_files.truncate(top_n);
+
+        TokenReport {
+            total_files: files.len(),
+            total_size,
+            total_tokens,
+            context_size,
+            percent_of_context: percent(total_tokens, context_size),
+            directories: to_entries(directories.into_iter().collect()),
+            file_types: to_entries(file_types.into_iter().collect()),
+            top_files,
+        }
+    }
+
+    /// Renders the report in the requested format
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Table => self.to_table(),
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Csv => self.to_csv(),
+        }
+    }
+
+    /// Renders the report as human-readable tables
+    pub fn to_table(&self) -> String {
+        let mut out = format!(
+            "Total: {} tokens across {} files ({} bytes), {:.1}% of a {}-token context\n",
+            self.total_tokens,
+            self.total_files,
+            self.total_size,
+            self.percent_of_context,
+            self.context_size
+        );
+
+        let sections = [
+            ("By directory", &self.directories),
+            ("By file type", &self.file_types),
+            ("Heaviest files", &self.top_files),
+        ];
+        for (title, entries) in sections {
+            out.push_str(&format!("\n{}\n", title));
+            out.push_str(&format!(
+                "{:>10} {:>8} {:>10} {:>6}  {}\n",
+                "TOKENS", "% TOTAL", "% CONTEXT", "FILES", "NAME"
+            ));
+            for entry in entries.iter() {
+                out.push_str(&format!(
+                    "{:>10} {:>7.1}% {:>9.1}% {:>6}  {}\n",
+                    entry.tokens,
+                    entry.percent_of_total,
+                    entry.percent_of_context,
+                    entry.files,
+                    entry.name
+                ));
+            }
+        }
+
+        out
+    }
+
+    /// Renders the report as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("TokenReport is always serializable")
+    }
+
+    /// Renders the report as CSV, with a `section` column telling the breakdowns apart
+    pub fn to_csv(&self) -> String {
+        let mut out =
+            String::from("section,name,files,size,tokens,percent_of_total,percent_of_context\n");
+        let sections = [
+            ("directory", &self.directories),
+            ("type", &self.file_types),
+            ("file", &self.top_files),
+        ];
+        for (section, entries) in sections {
+            for entry in entries.iter() {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{:.2},{:.2}\n",
+                    section,
+                    Self::escape_csv(&entry.name),
+                    entry.files,
+                    entry.size,
+                    entry.tokens,
+                    entry.percent_of_total,
+                    entry.percent_of_context
+                ));
+            }
+        }
+        out
+    }
+
+    fn escape_csv(field: &str) -> String {
+        if field.contains([',', '"', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, tokens: usize) -> FileTokens {
+        FileTokens {
+            path: Path::new("/repo").join(path),
+            size: tokens as u64 * 4,
+            tokens,
+        }
+    }
+
+    #[test]
+    fn test_build_report() {
+        let files = vec![
+            file("README.md", 10),
+            file("src/main.rs", 30),
+            file("src/cli/args.rs", 40),
+            file("tests/it.rs", 20),
+        ];
+        let report = TokenReport::build(&[PathBuf::from("/repo")], &files, 1000, 2);
+
+        assert_eq!(report.total_tokens, 100);
+        assert_eq!(report.total_files, 4);
+        assert!((report.percent_of_context - 10.0).abs() < f64::EPSILON);
+
+        // Directories are rolled up and sorted heaviest first
+        let dirs: Vec<_> = report
+            .directories
+            .iter()
+            .map(|e| (e.name.as_str(), e.tokens))
+            .collect();
+        assert_eq!(
+            dirs,
+            vec![
+                ("src/", 70),
+                ("src/cli/", 40),
+                ("tests/", 20),
+                ("(root)", 10)
+            ]
+        );
+
+        let types: Vec<_> = report
+            .file_types
+            .iter()
+            .map(|e| (e.name.as_str(), e.files))
+            .collect();
+        assert_eq!(types, vec![("rs", 3), ("md", 1)]);
+
+        assert_eq!(report.top_files.len(), 2);
+        assert_eq!(report.top_files[0].name, "src/cli/args.rs");
+        assert!((report.top_files[0].percent_of_total - 40.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_report_formats() {
+        let files = vec![file("a,b.txt", 5)];
+        let report = TokenReport::build(&[PathBuf::from("/repo")], &files, 100, 10);
+
+        let csv = report.to_csv();
+        assert!(csv.starts_with("section,name,"));
+        assert!(csv.contains("type,txt,1,20,5,100.00,5.00"));
+        assert!(csv.contains("file,\"a,b.txt\",1,20,5,100.00,5.00"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["total_tokens"], 5);
+        assert_eq!(json["top_files"][0]["name"], "a,b.txt");
+
+        assert!(report.to_table().contains("Heaviest files"));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::error::PromptPalError;
+use crate::service::{ContextOptions, ContextService};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderMap, ALLOW, CONTENT_TYPE, HOST, ORIGIN};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use serde_json::json;
+use std::convert::Infallible;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Largest request body accepted, which is plenty for options
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Endpoints of the API, each taking [`ContextOptions`] as a JSON body
+pub const ENDPOINTS: &[&str] = &["/scan", "/count", "/render", "/report"];
+
+/// Serves the HTTP/JSON API for `service` on `listener` until an error stops it
+///
+/// Each endpoint accepts `POST` with a JSON object of [`ContextOptions`], or `GET`
+/// to use the service's settings as they are:
+///
+/// - `/scan`: the files that would be scanned, with their sizes
+/// - `/count`: the tokens in each file a prompt could include
+/// - `/render`: the rendered prompt as `prompt`, with its `report`
+/// - `/report`: token usage by directory, file type and heaviest files
+///
+/// Errors are returned as `{"error": "..."}` with a 4xx or 5xx status. Requests
+/// that may come from a web page are refused: the `Host` must be `localhost` or an IP
+/// address, an `Origin` must match it, and a `POST` body must be `application/json`.
+pub async fn serve(service: Arc<ContextService>, listener: TcpListener) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let service = service.clone();
+        tokio::spawn(async move {
+            let handler = service_fn(move |request| handle(service.clone(), request));
+            // A client hanging up mid-request only affects that connection
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), handler)
+                .await;
+        });
+    }
+}
+
+async fn handle(
+    service: Arc<ContextService>,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let path = request.uri().path().to_string();
+    if !ENDPOINTS.contains(&path.as_str()) {
+        let message = format!("no endpoint {}; try one of {}", path, ENDPOINTS.join(", "));
+        return Ok(error(StatusCode::NOT_FOUND, message));
+    }
+    if request.method() != Method::GET && request.method() != Method::POST {
+        let mut response = error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            format!("{} takes GET or POST", path),
+        );
+        response
+            .headers_mut()
+            .insert(ALLOW, "GET, POST".parse().expect("Allow header is valid"));
+        return Ok(response);
+    }
+    if let Some(message) = cross_site(request.headers()) {
+        return Ok(error(StatusCode::FORBIDDEN, message));
+    }
+    if request.method() == Method::POST && !is_json(request.headers()) {
+        let message = format!("{} takes a body of type application/json", path);
+        return Ok(error(StatusCode::UNSUPPORTED_MEDIA_TYPE, message));
+    }
+
+    let body = match Limited::new(request.into_body(), MAX_BODY_SIZE)
+        .collect()
+        .await
+    {
+        Ok(body) => body.to_bytes(),
+        Err(e) => return Ok(error(StatusCode::BAD_REQUEST, e.to_string())),
+    };
+    let options: ContextOptions = if body.iter().all(u8::is_ascii_whitespace) {
+        ContextOptions::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(options) => options,
+            Err(e) => {
+                let message = format!("invalid options: {}", e);
+                return Ok(error(StatusCode::BAD_REQUEST, message));
+            }
+        }
+    };
+
+    // Scanning and tokenizing block, so keep them off the connection tasks
+    let result = tokio::task::spawn_blocking(move || {
+        let result = match path.as_str() {
+            "/scan" => service.scan(&options).map(to_json),
+            "/count" => service.count(&options).map(to_json),
+            "/render" => service.render(&options).map(|prompt| {
+                json!({
+                    "prompt": prompt.text,
+                    "report": prompt.report,
+                })
+            }),
+            _ => service.report(&options).map(to_json),
+        };
+        if let Err(e) = service.save_cache() {
+            eprintln!("Warning: failed to save the cache: {}", e);
+        }
+        result
+    })
+    .await;
+
+    Ok(match result {
+        Ok(Ok(value)) => json_response(StatusCode::OK, &value),
+        Ok(Err(e)) => error(status(&e), e.to_string()),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    })
+}
+
+/// Says why a request may have been sent by a web page rather than a local tool
+///
+/// A page that rebinds its own domain name to this address still sends that name as
+/// the `Host`, and browsers send the page's `Origin` with cross-site requests.
+fn cross_site(headers: &HeaderMap) -> Option<String> {
+    let host = headers.get(HOST).and_then(|host| host.to_str().ok());
+    let Some(host) = host.filter(|host| is_local(host)) else {
+        return Some("requests must be addressed to localhost or an IP address".into());
+    };
+    let origin = headers.get(ORIGIN)?;
+    let authority = origin.to_str().ok().and_then(|origin| {
+        let (_, authority) = origin.split_once("://")?;
+        Some(authority)
+    });
+    match authority {
+        Some(authority) if authority.eq_ignore_ascii_case(host) => None,
+        _ => Some("cross-origin requests are not allowed".into()),
+    }
+}
+
+/// Returns true if `host`, with or without a port, is `localhost` or an IP address
+fn is_local(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok()
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Picks the status for an error: the client's fault unless the server couldn't read
+fn status(error: &PromptPalError) -> StatusCode {
+    match error {
+        PromptPalError::MissingRoot { .. } | PromptPalError::NotADirectory { .. } => {
+            StatusCode::NOT_FOUND
+        }
+        // Asking for files that aren't scanned, such as excluded ones
+        PromptPalError::Io { source, .. } if source.kind() == io::ErrorKind::NotFound => {
+            StatusCode::NOT_FOUND
+        }
+        PromptPalError::PermissionDenied { .. }
+        | PromptPalError::Output { .. }
+        | PromptPalError::Io { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::UNPROCESSABLE_ENTITY,
+    }
+}
+
+fn to_json(value: impl Serialize) -> serde_json::Value {
+    serde_json::to_value(value).expect("Results serialize to JSON")
+}
+
+fn error(status: StatusCode, message: String) -> Response<Full<Bytes>> {
+    json_response(status, &json!({ "error": message }))
+}
+
+fn json_response(status: StatusCode, value: &serde_json::Value) -> Response<Full<Bytes>> {
+    let mut body = value.to_string();
+    body.push('\n');
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        "application/json".parse().expect("Content type is valid"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Sends a request over a fresh connection and returns the status line and body
+    async fn send(addr: std::net::SocketAddr, request: &str) -> (String, serde_json::Value) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn post(path: &str, body: &str) -> String {
+        format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            body.len(),
+            body
+        )
+    }
+
+    #[tokio::test]
+    async fn test_serve_endpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.path().join("notes.md"), "# Notes\n").unwrap();
+        let service = ContextService::new(dir.path(), Settings::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(Arc::new(service), listener));
+
+        let (status, body) = send(
+            addr,
+            "GET /scan HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body["files"].as_array().unwrap().len(), 2);
+
+        let (_, body) = send(addr, &post("/count", r#"{"include": ["*.rs"]}"#)).await;
+        assert_eq!(body["files"][0]["path"], "main.rs");
+        assert_eq!(body["total_tokens"], body["files"][0]["tokens"]);
+
+        let (_, body) = send(
+            addr,
+            &post(
+                "/render",
+                r#"{"instructions": "Explain", "format": "markdown"}"#,
+            ),
+        )
+        .await;
+        let prompt = body["prompt"].as_str().unwrap();
+        assert!(prompt.contains("Explain") && prompt.contains("fn main() {}"));
+        assert_eq!(body["report"]["files"].as_array().unwrap().len(), 2);
+
+        let (status, body) = send(addr, &post("/report", r#"{"budgett": 5}"#)).await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert!(body["error"].as_str().unwrap().contains("budgett"));
+        let (status, _) = send(addr, &post("/render", r#"{"path": "../.."}"#)).await;
+        assert_eq!(status, "HTTP/1.1 422 Unprocessable Entity");
+        let (status, _) = send(addr, &post("/files", "")).await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[tokio::test]
+    async fn test_serve_refuses_web_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        let service = ContextService::new(dir.path(), Settings::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(Arc::new(service), listener));
+
+        // A rebound domain name, a cross-site page, and a form post
+        let rebound = post("/render", "{}").replace("Host: localhost", "Host: evil.example:7878");
+        let (status, body) = send(addr, &rebound).await;
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+        assert!(body.get("prompt").is_none());
+        let cross_site = post("/render", "{}").replace(
+            "Connection: close",
+            "Connection: close\r\nOrigin: https://evil.example",
+        );
+        let (status, _) = send(addr, &cross_site).await;
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+        let form = post("/render", "{}").replace("application/json", "text/plain");
+        let (status, _) = send(addr, &form).await;
+        assert_eq!(status, "HTTP/1.1 415 Unsupported Media Type");
+
+        // Local tools, and pages served from the same origin, get through
+        let local = post("/scan", "{}").replace("Host: localhost", &format!("Host: {}", addr));
+        let (status, _) = send(addr, &local).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let same_origin = post("/scan", "{}").replace(
+            "Connection: close",
+            "Connection: close\r\nOrigin: http://localhost",
+        );
+        let (status, _) = send(addr, &same_origin).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+    }
+}
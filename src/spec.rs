@@ -0,0 +1,208 @@
+use crate::error::{PromptPalError, Result};
+use crate::lines::LineRange;
+use crate::outline::find_symbol;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Part of a file selected by a [`FileSpec`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Region {
+    /// 1-based lines, inclusive; an `end` of `None` runs to the end of the file
+    Lines { start: usize, end: Option<usize> },
+    /// A named item such as `parse_header`, optionally after its keyword (`fn parse_header`)
+    Symbol(String),
+}
+
+impl Region {
+    /// Finds the lines the region covers in `content`
+    ///
+    /// Line ranges running past the end of the file are cut short.
+    pub fn resolve(&self, path: &Path, content: &str) -> std::result::Result<LineRange, String> {
+        let line_count = content.lines().count();
+        match self {
+            Region::Lines { start, end } => {
+                if *start > line_count {
+                    return Err(format!(
+                        "line {} is past the end of the file ({} lines)",
+                        start, line_count
+                    ));
+                }
+                Ok(LineRange {
+                    start: *start,
+                    end: end.map_or(line_count, |end| end.min(line_count)),
+                })
+            }
+            Region::Symbol(symbol) => find_symbol(path, content, symbol)
+                .map(|lines| LineRange {
+                    start: lines.start + 1,
+                    end: lines.end,
+                })
+                .ok_or_else(|| format!("symbol `{}` not found", symbol)),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Lines { start, end: None } => write!(f, "{}-", start),
+            Region::Lines {
+                start,
+                end: Some(end),
+            } if start == end => write!(f, "{}", start),
+            Region::Lines {
+                start,
+                end: Some(end),
+            } => write!(f, "{}-{}", start, end),
+            Region::Symbol(symbol) => f.write_str(symbol),
+        }
+    }
+}
+
+/// A file to include, optionally narrowed to a region of it
+///
+/// Written as `path`, `path:120-340` (or `path:120`, `path:120-`) for lines, or
+/// `path:parse_header` / `path:fn parse_header` for a symbol. A path without a
+/// region may also name a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSpec {
+    /// Relative to a scanned directory, or prefixed with it
+    pub path: PathBuf,
+    pub region: Option<Region>,
+}
+
+impl FileSpec {
+    /// Reads a file list: one spec per line, skipping blank lines and `#` comments
+    pub fn read_list(path: &Path) -> Result<Vec<FileSpec>> {
+        let content = fs::read_to_string(path).map_err(|e| PromptPalError::from_io(path, e))?;
+        content
+            .lines()
+            .enumerate()
+            .map(|(number, line)| (number, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(number, line)| {
+                line.parse().map_err(|message| PromptPalError::Config {
+                    path: path.to_path_buf(),
+                    message: format!("line {}: {}", number + 1, message),
+                })
+            })
+            .collect()
+    }
+}
+
+impl FromStr for FileSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> std::result::Result<Self, String> {
+        let Some((path, region)) = spec.split_once(':') else {
+            return Ok(FileSpec {
+                path: PathBuf::from(spec),
+                region: None,
+            });
+        };
+        if path.is_empty() {
+            return Err(format!("missing path in '{}'", spec));
+        }
+        let region = region.trim();
+        let region = if region.starts_with(|c: char| c.is_ascii_digit()) {
+            parse_lines(region).ok_or_else(|| format!("invalid line range '{}'", region))?
+        } else {
+            let words: Vec<_> = region.split_whitespace().collect();
+            let is_name = |word: &str| {
+                !word.is_empty()
+                    && word
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '_' || c == '$' || c == '!')
+            };
+            if words.is_empty() || words.len() > 2 || !words.iter().all(|w| is_name(w)) {
+                return Err(format!(
+                    "expected a line range or symbol after ':' in '{}'",
+                    spec
+                ));
+            }
+            Region::Symbol(words.join(" "))
+        };
+        Ok(FileSpec {
+            path: PathBuf::from(path),
+            region: Some(region),
+        })
+    }
+}
+
+/// Parses `120`, `120-340` or `120-`
+fn parse_lines(range: &str) -> Option<Region> {
+    let (start, end) = match range.split_once('-') {
+        Some((start, "")) => (start.parse().ok()?, None),
+        Some((start, end)) => (start.parse().ok()?, Some(end.parse().ok()?)),
+        None => {
+            let line = range.parse().ok()?;
+            (line, Some(line))
+        }
+    };
+    (start > 0 && end.is_none_or(|end| end >= start)).then_some(Region::Lines { start, end })
+}
+
+impl fmt::Display for FileSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        match &self.region {
+            Some(region) => write!(f, ":{}", region),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_specs() {
+        let spec: FileSpec = "src/big.rs:120-340".parse().unwrap();
+        assert_eq!(spec.path, PathBuf::from("src/big.rs"));
+        assert_eq!(
+            spec.region,
+            Some(Region::Lines {
+                start: 120,
+                end: Some(340)
+            })
+        );
+        assert_eq!(spec.to_string(), "src/big.rs:120-340");
+
+        let spec: FileSpec = "src/lib.rs:fn parse_header".parse().unwrap();
+        assert_eq!(spec.region, Some(Region::Symbol("fn parse_header".into())));
+        assert_eq!("src/lib.rs".parse::<FileSpec>().unwrap().region, None);
+        assert_eq!(
+            "a.rs:7-".parse::<FileSpec>().unwrap().to_string(),
+            "a.rs:7-"
+        );
+
+        for invalid in ["a.rs:0-3", "a.rs:9-3", "a.rs:", "a.rs:x y z", ":3"] {
+            assert!(invalid.parse::<FileSpec>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_resolve_region() {
+        let content = "a\nb\nc\n";
+        let path = Path::new("notes.txt");
+        let lines = Region::Lines {
+            start: 2,
+            end: Some(10),
+        };
+        assert_eq!(
+            lines.resolve(path, content),
+            Ok(LineRange { start: 2, end: 3 })
+        );
+        let past_end = Region::Lines {
+            start: 4,
+            end: None,
+        };
+        assert!(past_end.resolve(path, content).is_err());
+        assert!(Region::Symbol("main".into())
+            .resolve(path, content)
+            .is_err());
+    }
+}
@@ -0,0 +1,390 @@
+use crate::fileinfo::detect_file_type;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Content that can be stripped from files to save tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strip {
+    /// Comments, leaving string literals that look like comments alone
+    Comments,
+    /// Blank lines
+    Blank,
+    /// A license or copyright comment block at the top of the file
+    License,
+}
+
+/// Tokens saved by stripping a file
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenSavings {
+    pub path: PathBuf,
+    /// Tokens before stripping
+    pub before: usize,
+    /// Tokens after stripping
+    pub after: usize,
+}
+
+impl TokenSavings {
+    pub fn saved(&self) -> usize {
+        self.before.saturating_sub(self.after)
+    }
+}
+
+/// Comment syntax of a family of languages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    /// `//` and `/* */`, as in C, Rust, Go, JS/TS and Java
+    CStyle { rust: bool, backtick: bool },
+    /// `#` comments; `word_start` requires whitespace before the `#`, as in shell
+    Hash {
+        word_start: bool,
+        triple_quotes: bool,
+    },
+    /// `--` and `/* */`
+    Sql,
+    /// `--` and `--[[ ]]`
+    Lua,
+    /// `<!-- -->`
+    Markup,
+}
+
+impl Syntax {
+    fn detect(path: &Path) -> Option<Self> {
+        let syntax = match detect_file_type(path).as_str() {
+            "rs" => Syntax::CStyle {
+                rust: true,
+                backtick: false,
+            },
+            "js" | "jsx" | "ts" | "tsx" | "mjs" | "cjs" | "mts" | "cts" | "go" => Syntax::CStyle {
+                rust: false,
+                backtick: true,
+            },
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" | "java" | "kt" | "kts"
+            | "scala" | "cs" | "swift" | "dart" | "php" | "css" | "scss" | "less" | "proto" => {
+                Syntax::CStyle {
+                    rust: false,
+                    backtick: false,
+                }
+            }
+            "py" | "pyi" => Syntax::Hash {
+                word_start: false,
+                triple_quotes: true,
+            },
+            "rb" | "pl" | "r" => Syntax::Hash {
+                word_start: false,
+                triple_quotes: false,
+            },
+            "sh" | "bash" | "zsh" | "fish" | "yaml" | "yml" | "toml" | "ini" | "cfg" | "conf"
+            | "mk" | "cmake" | "dockerfile" => Syntax::Hash {
+                word_start: true,
+                triple_quotes: false,
+            },
+            "sql" => Syntax::Sql,
+            "lua" => Syntax::Lua,
+            "html" | "htm" | "xml" | "svg" | "md" | "vue" => Syntax::Markup,
+            _ => return None,
+        };
+        Some(syntax)
+    }
+}
+
+/// Strips the requested content from a file, based on its language
+///
+/// Comments are only stripped from languages with a known comment syntax; blank
+/// lines are stripped from any file. Lines left empty by stripping a comment are
+/// dropped too.
+pub fn strip(path: &Path, content: &str, strip: &[Strip]) -> String {
+    strip_lines(path, content, strip).0
+}
+
+/// Like [`strip`], but also returns the 0-based line of `content` each kept line came from
+pub fn strip_lines(path: &Path, content: &str, strip: &[Strip]) -> (String, Vec<usize>) {
+    let syntax = Syntax::detect(path);
+    let mut removed: Vec<Range<usize>> = Vec::new();
+    if let Some(syntax) = syntax {
+        let comments = comment_spans(syntax, content);
+        if strip.contains(&Strip::Comments) {
+            removed = comments;
+        } else if strip.contains(&Strip::License) {
+            removed = license_header(content, &comments);
+        }
+    }
+
+    let mut stripped = String::with_capacity(content.len());
+    let mut cursor = 0;
+    for span in &removed {
+        stripped.push_str(&content[cursor..span.start]);
+        // Keep the line structure so stripped lines can be matched up with the original
+        stripped.extend(content[span.clone()].chars().filter(|&c| c == '\n'));
+        cursor = span.end;
+    }
+    stripped.push_str(&content[cursor..]);
+
+    // Stripped content has the same lines as the original, minus what was removed from them
+    let mut originals: Vec<_> = content.split('\n').collect();
+    let mut lines: Vec<_> = stripped.split('\n').collect();
+    let ends_with_newline = content.ends_with('\n');
+    if ends_with_newline {
+        originals.pop();
+        lines.pop();
+    }
+
+    let mut out = String::with_capacity(stripped.len());
+    let mut sources = Vec::new();
+    for (number, (original, line)) in originals.into_iter().zip(lines).enumerate() {
+        let emptied = line.trim().is_empty() && !original.trim().is_empty();
+        let blank = original.trim().is_empty() && strip.contains(&Strip::Blank);
+        if emptied || blank {
+            continue;
+        }
+        if line == original {
+            out.push_str(line);
+        } else {
+            out.push_str(line.trim_end());
+        }
+        out.push('\n');
+        sources.push(number);
+    }
+    if !ends_with_newline {
+        out.pop();
+    }
+    (out, sources)
+}
+
+/// Returns the comments that make up a license header, if the file starts with one
+fn license_header(content: &str, comments: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut header = Vec::new();
+    let mut cursor = 0;
+    // A shebang line comes before the license
+    if content.starts_with("#!") {
+        cursor = content.find('\n').map_or(content.len(), |i| i + 1);
+    }
+    for comment in comments {
+        if comment.start < cursor {
+            break;
+        }
+        // The header ends at the first code or blank line
+        let gap = &content[cursor..comment.start];
+        if !gap.trim().is_empty() || (!header.is_empty() && gap.matches('\n').count() > 1) {
+            break;
+        }
+        header.push(comment.clone());
+        cursor = comment.end;
+    }
+
+    let text = header
+        .iter()
+        .map(|span| content[span.clone()].to_lowercase())
+        .collect::<String>();
+    if ["license", "copyright", "spdx-license-identifier", "(c)"]
+        .iter()
+        .any(|marker| text.contains(marker))
+    {
+        header
+    } else {
+        Vec::new()
+    }
+}
+
+/// Returns the byte ranges of every comment in `content`, skipping string literals
+fn comment_spans(syntax: Syntax, content: &str) -> Vec<Range<usize>> {
+    let bytes = content.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    let line_end = |from: usize| content[from..].find('\n').map_or(bytes.len(), |n| from + n);
+    let find = |from: usize, needle: &str| {
+        content[from..]
+            .find(needle)
+            .map_or(bytes.len(), |n| from + n + needle.len())
+    };
+
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        let at_word_start = i == 0 || bytes[i - 1].is_ascii_whitespace();
+        let comment = match syntax {
+            Syntax::CStyle { .. } if rest.starts_with(b"//") => Some(line_end(i)),
+            Syntax::CStyle { .. } | Syntax::Sql if rest.starts_with(b"/*") => {
+                Some(find(i + 2, "*/"))
+            }
+            Syntax::Sql if rest.starts_with(b"--") => Some(line_end(i)),
+            Syntax::Lua if rest.starts_with(b"--[[") => Some(find(i + 4, "]]")),
+            Syntax::Lua if rest.starts_with(b"--") => Some(line_end(i)),
+            Syntax::Markup if rest.starts_with(b"<!--") => Some(find(i + 4, "-->")),
+            Syntax::Hash { word_start, .. }
+                if rest[0] == b'#'
+                    && (!word_start || at_word_start)
+                    && !rest.starts_with(b"#!") =>
+            {
+                Some(line_end(i))
+            }
+            // A shebang after the first line is an ordinary comment
+            Syntax::Hash { .. } if rest.starts_with(b"#!") && i > 0 => Some(line_end(i)),
+            _ => None,
+        };
+        if let Some(end) = comment {
+            spans.push(i..end);
+            i = end;
+            continue;
+        }
+
+        i = match syntax {
+            Syntax::Markup => i + 1,
+            _ => skip_literal(syntax, bytes, i).unwrap_or(i + 1),
+        };
+    }
+    spans
+}
+
+/// Returns the offset just past a string or char literal starting at `i`, if there is one
+fn skip_literal(syntax: Syntax, bytes: &[u8], i: usize) -> Option<usize> {
+    let rest = &bytes[i..];
+    let until = |from: usize, close: &[u8], escapes: bool| {
+        let mut j = from;
+        while j < bytes.len() {
+            if escapes && bytes[j] == b'\\' {
+                j += 2;
+                continue;
+            }
+            if bytes[j..].starts_with(close) {
+                return j + close.len();
+            }
+            j += 1;
+        }
+        bytes.len()
+    };
+
+    match (syntax, rest[0]) {
+        (
+            Syntax::Hash {
+                triple_quotes: true,
+                ..
+            },
+            b'"' | b'\'',
+        ) if rest.starts_with(b"\"\"\"") || rest.starts_with(b"'''") => {
+            Some(until(i + 3, &rest[..3], true))
+        }
+        (Syntax::CStyle { rust: true, .. }, b'r')
+            if (i == 0 || !bytes[i - 1].is_ascii_alphanumeric())
+                && rest[1..]
+                    .iter()
+                    .find(|&&b| b != b'#')
+                    .is_some_and(|&b| b == b'"') =>
+        {
+            let hashes = rest[1..].iter().take_while(|&&b| b == b'#').count();
+            let mut close = vec![b'"'];
+            close.extend(std::iter::repeat_n(b'#', hashes));
+            Some(until(i + hashes + 2, &close, false))
+        }
+        (Syntax::CStyle { rust: true, .. }, b'\'') => {
+            // A char literal, or a lifetime which needs no skipping
+            if rest.get(1) == Some(&b'\\') {
+                Some(until(i + 3, b"'", false))
+            } else {
+                let width = match rest.get(1)? {
+                    b if *b < 0x80 => 1,
+                    b if *b >= 0xf0 => 4,
+                    b if *b >= 0xe0 => 3,
+                    _ => 2,
+                };
+                (rest.get(1 + width) == Some(&b'\'')).then_some(i + width + 2)
+            }
+        }
+        (Syntax::CStyle { backtick: true, .. }, b'`') => Some(until(i + 1, b"`", true)),
+        (
+            Syntax::Hash {
+                word_start: true, ..
+            },
+            b'\'',
+        ) => Some(until(i + 1, b"'", false)),
+        // Rust, shell, Ruby and Perl strings can span lines
+        (
+            Syntax::CStyle { rust: true, .. }
+            | Syntax::Hash {
+                triple_quotes: false,
+                ..
+            },
+            quote @ (b'"' | b'\''),
+        ) => Some(until(i + 1, &[quote], true)),
+        (_, quote @ (b'"' | b'\'')) => {
+            // Other strings end at the line, even when left unterminated
+            let end = until(i + 1, &[quote], true);
+            let newline = bytes[i + 1..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(bytes.len(), |n| i + 1 + n);
+            Some(end.min(newline))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_comments_keeps_strings() {
+        let source = "// Module docs\nfn main() {\n    let url = \"http://example.com\"; // trailing\n    /* block\n       comment */\n    let c = '/';\n    let s = r#\"/* not a comment */\"#;\n}\n";
+        assert_eq!(
+            strip(Path::new("main.rs"), source, &[Strip::Comments]),
+            "fn main() {\n    let url = \"http://example.com\";\n    let c = '/';\n    let s = r#\"/* not a comment */\"#;\n}\n"
+        );
+
+        let source = "#!/usr/bin/env python\nx = \"# not a comment\"  # comment\n\"\"\"\n# docstring line\n\"\"\"\n";
+        assert_eq!(
+            strip(Path::new("run.py"), source, &[Strip::Comments]),
+            "#!/usr/bin/env python\nx = \"# not a comment\"\n\"\"\"\n# docstring line\n\"\"\"\n"
+        );
+
+        let source = "echo ${#items} # count\n";
+        assert_eq!(
+            strip(Path::new("run.sh"), source, &[Strip::Comments]),
+            "echo ${#items}\n"
+        );
+
+        // Strings spanning lines keep every line
+        let source = "let sql = \"SELECT 1\n// keep this line\nFROM t\"; // trailing\n";
+        assert_eq!(
+            strip(Path::new("query.rs"), source, &[Strip::Comments]),
+            "let sql = \"SELECT 1\n// keep this line\nFROM t\";\n"
+        );
+        let source = "echo \"a\n# still string\nb\" # comment\n";
+        assert_eq!(
+            strip(Path::new("run.sh"), source, &[Strip::Comments]),
+            "echo \"a\n# still string\nb\"\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_license_and_blank() {
+        let source = "/*\n * Copyright 2024 Example Corp\n * Licensed under the MIT License\n */\n\n// Entry point\nfn main() {\n\n\n    run();\n}\n";
+        assert_eq!(
+            strip(Path::new("main.rs"), source, &[Strip::License]),
+            "\n// Entry point\nfn main() {\n\n\n    run();\n}\n"
+        );
+        assert_eq!(
+            strip(
+                Path::new("main.rs"),
+                source,
+                &[Strip::License, Strip::Blank]
+            ),
+            "// Entry point\nfn main() {\n    run();\n}\n"
+        );
+
+        let (_, sources) = strip_lines(
+            Path::new("main.rs"),
+            source,
+            &[Strip::License, Strip::Blank],
+        );
+        assert_eq!(sources, vec![5, 6, 9, 10]);
+
+        // A leading comment without license terms is kept
+        let source = "// Entry point\nfn main() {}";
+        assert_eq!(
+            strip(Path::new("main.rs"), source, &[Strip::License]),
+            source
+        );
+    }
+}
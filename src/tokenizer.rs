@@ -1,18 +1,63 @@
+use clap::ValueEnum;
+use serde::Deserialize;
 use std::sync::OnceLock;
-use tiktoken_rs::{cl100k_base, model::get_context_size, CoreBPE};
+use tiktoken_rs::{
+    cl100k_base, model::get_context_size, o200k_base, p50k_base, r50k_base, CoreBPE,
+};
 
-/// A thread-safe, lazily initialized tokenizer for GPT models
-static TOKENIZER: OnceLock<CoreBPE> = OnceLock::new();
+/// Thread-safe, lazily initialized tokenizers for GPT models, one per encoding
+static CL100K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+static O200K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+static P50K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+static R50K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+
+/// BPE encodings available for token counting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    /// GPT-3.5 / GPT-4
+    #[default]
+    Cl100kBase,
+    /// GPT-4o
+    O200kBase,
+    /// Codex / text-davinci-002 and -003
+    P50kBase,
+    /// GPT-3
+    R50kBase,
+}
+
+impl Encoding {
+    /// Counts the tokens in a string using this encoding
+    pub fn count(self, text: &str) -> usize {
+        self.bpe().encode_with_special_tokens(text).len()
+    }
+
+    fn bpe(self) -> &'static CoreBPE {
+        match self {
+            Encoding::Cl100kBase => CL100K_BASE
+                .get_or_init(|| cl100k_base().expect("Failed to load cl100k_base tokenizer")),
+            Encoding::O200kBase => O200K_BASE
+                .get_or_init(|| o200k_base().expect("Failed to load o200k_base tokenizer")),
+            Encoding::P50kBase => {
+                P50K_BASE.get_or_init(|| p50k_base().expect("Failed to load p50k_base tokenizer"))
+            }
+            Encoding::R50kBase => {
+                R50K_BASE.get_or_init(|| r50k_base().expect("Failed to load r50k_base tokenizer"))
+            }
+        }
+    }
+}
 
 /// Gets the number of tokens in a string using GPT-3.5/4 tokenizer (cl100k_base)
 pub fn count_tokens(text: &str, model: Option<&str>) -> (usize, usize) {
-    let tokenizer =
-        TOKENIZER.get_or_init(|| cl100k_base().expect("Failed to load cl100k_base tokenizer"));
+    let token_count = Encoding::Cl100kBase.count(text);
 
-    let token_count = tokenizer.encode_with_special_tokens(text).len();
-    let context_size = get_context_size(model.unwrap_or("gpt-3.5-turbo"));
+    (token_count, context_size(model))
+}
 
-    (token_count, context_size)
+/// Gets the context window size of a model, defaulting to gpt-3.5-turbo
+pub fn context_size(model: Option<&str>) -> usize {
+    get_context_size(model.unwrap_or("gpt-3.5-turbo"))
 }
 
 #[cfg(test)]
@@ -47,4 +92,11 @@ mod tests {
         let (count, _) = count_tokens(long_text, None);
         assert!(count > 20);
     }
+
+    #[test]
+    fn test_encodings() {
+        assert_eq!(Encoding::Cl100kBase.count("Hello, world!"), 4);
+        assert_eq!(Encoding::O200kBase.count("Hello, world!"), 4);
+        assert_eq!(Encoding::R50kBase.count(""), 0);
+    }
 }
@@ -0,0 +1,152 @@
+use crate::fileinfo::FileInfo;
+use crate::render::RenderOptions;
+use crate::tokenizer::count_tokens;
+use std::collections::HashMap;
+
+/// A node in the directory tree built from a list of files
+#[derive(Default)]
+pub(crate) struct FileNode<'a> {
+    pub name: String,
+    pub file_info: Option<&'a FileInfo>,
+    pub tokens: usize,
+    pub children: HashMap<String, FileNode<'a>>,
+}
+
+impl<'a> FileNode<'a> {
+    /// Builds a directory tree from the components of each file's displayed path
+    pub fn build(files: &'a [FileInfo], options: &RenderOptions) -> FileNode<'a> {
+        let mut root = FileNode::default();
+
+        for file in files {
+            let path_components: Vec<_> = options
+                .display_path(&file.path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            let mut current = &mut root;
+
+            // Create the path hierarchy
+            for (i, component) in path_components.iter().enumerate() {
+                let is_last = i == path_components.len() - 1;
+                current = current
+                    .children
+                    .entry(component.clone())
+                    .or_insert_with(|| FileNode {
+                        name: component.clone(),
+                        file_info: if is_last { Some(file) } else { None },
+                        tokens: if is_last {
+                            file.tokens.unwrap_or_else(|| {
+                                count_tokens(file.content.as_deref().unwrap_or_default(), None).0
+                            })
+                        } else {
+                            0
+                        },
+                        children: HashMap::new(),
+                    });
+            }
+        }
+
+        root
+    }
+
+    /// Returns the children of this node sorted by name for consistent output
+    pub fn sorted_children(&self) -> Vec<&FileNode<'a>> {
+        let mut sorted_children: Vec<_> = self.children.values().collect();
+        sorted_children.sort_by(|a, b| a.name.cmp(&b.name));
+        sorted_children
+    }
+
+    /// Returns the total tokens and bytes of this node, including all descendants
+    pub fn totals(&self) -> (usize, u64) {
+        let own = (
+            self.tokens,
+            self.file_info.as_ref().map_or(0, |info| info.size),
+        );
+        self.children.values().fold(own, |(tokens, size), child| {
+            let (child_tokens, child_size) = child.totals();
+            (tokens + child_tokens, size + child_size)
+        })
+    }
+
+    /// Renders the tree as `├──`/`└──` lines, optionally annotated with token counts and sizes
+    pub fn tree_view(&self, annotate: bool) -> String {
+        let mut result = String::new();
+        Self::generate_tree_view_recursive(self, "", "", annotate, &mut result);
+        result
+    }
+
+    fn generate_tree_view_recursive(
+        node: &FileNode,
+        prefix: &str,
+        name: &str,
+        annotate: bool,
+        result: &mut String,
+    ) {
+        if !name.is_empty() {
+            if annotate {
+                let (tokens, size) = node.totals();
+                result.push_str(&format!(
+                    "{}{} ({} tokens, {})\n",
+                    prefix,
+                    name,
+                    tokens,
+                    format_size(size)
+                ));
+            } else {
+                result.push_str(&format!("{}{}\n", prefix, name));
+            }
+        }
+
+        let sorted_children = node.sorted_children();
+
+        for (i, child) in sorted_children.iter().enumerate() {
+            let is_last = i == sorted_children.len() - 1;
+            let (next_prefix, pointer) = if is_last {
+                (format!("{}    ", prefix), "└── ")
+            } else {
+                (format!("{}│   ", prefix), "├── ")
+            };
+
+            let display_name = if child.file_info.is_some() {
+                &child.name
+            } else {
+                &format!("{}/", child.name)
+            };
+
+            Self::generate_tree_view_recursive(
+                child,
+                &next_prefix,
+                &format!("{}{}", pointer, display_name),
+                annotate,
+                result,
+            );
+        }
+    }
+}
+
+/// Formats a byte count for display, e.g. `512 B` or `2.1 KB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(2150), "2.1 KB");
+        assert_eq!(format_size(3 * 1024 * 1024), "3.0 MB");
+    }
+}
@@ -0,0 +1,655 @@
+use crate::diff::unified_diff;
+use crate::error::{PromptPalError, Result, Warning};
+use crate::lines::LineRange;
+use crate::walkdir::STATE_DIR_NAME;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+/// A file as written in an LLM response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseFile {
+    /// Path as given in `<path>`
+    pub path: String,
+    /// Content with the CDATA wrapping and any line-number gutter removed
+    pub content: String,
+    /// Lines of the existing file the content replaces, from `<file range="a-b">`
+    pub range: Option<LineRange>,
+    /// True for `<content mode="outline">`, which can't be written back
+    pub outline: bool,
+}
+
+/// Parses the `<file>` elements of a response in the shape [`XmlGenerator`] writes
+///
+/// ```xml
+/// <file>
+///   <path>src/lib.rs</path>
+///   <content>
+///     <![CDATA[
+/// pub mod cli;
+///     ]]>
+///   </content>
+/// </file>
+/// ```
+///
+/// Text around the elements (explanations, markdown fences) is ignored, as are
+/// elements without a `<path>` or `<content>`.
+///
+/// [`XmlGenerator`]: crate::XmlGenerator
+pub fn parse_response(text: &str) -> Vec<ResponseFile> {
+    let mut files = Vec::new();
+    let mut rest = text;
+    while let Some((attrs, after)) = open_tag(rest, "file") {
+        rest = &rest[after..];
+        if let Some((file, end)) = parse_file(rest, attrs) {
+            files.push(file);
+            rest = &rest[end..];
+        }
+    }
+    files
+}
+
+/// Parses one `<file>` body, returning the file and the offset just past `</file>`
+fn parse_file(body: &str, attrs: &str) -> Option<(ResponseFile, usize)> {
+    let (_, path_start) = open_tag(body, "path")?;
+    let path_end = path_start + body[path_start..].find("</path>")?;
+    let path = unescape_xml(body[path_start..path_end].trim());
+
+    let (content_attrs, content_start) = open_tag(&body[path_end..], "content")?;
+    let content_start = path_end + content_start;
+    let (raw, content_end) = read_content(&body[content_start..])?;
+    let content_end = content_start + content_end;
+    let end = content_end
+        + body[content_end..]
+            .find("</file>")
+            .map_or(0, |i| i + "</file>".len());
+
+    let mut content = raw;
+    if attribute(content_attrs, "start_line").is_some() {
+        content = strip_gutter(&content).unwrap_or(content);
+    }
+    let range = attribute(attrs, "range").and_then(|range| {
+        let (start, end) = range.split_once('-')?;
+        Some(LineRange {
+            start: start.trim().parse().ok()?,
+            end: end.trim().parse().ok()?,
+        })
+    });
+    let file = ResponseFile {
+        path,
+        content,
+        range,
+        outline: attribute(content_attrs, "mode") == Some("outline"),
+    };
+    Some((file, end))
+}
+
+/// Finds the next `<name ...>` tag, returning its attributes and the offset past its `>`
+fn open_tag<'a>(text: &'a str, name: &str) -> Option<(&'a str, usize)> {
+    let pattern = format!("<{}", name);
+    let mut from = 0;
+    while let Some(found) = text[from..].find(&pattern) {
+        let start = from + found + pattern.len();
+        match text[start..].chars().next() {
+            Some('>') => return Some(("", start + 1)),
+            Some(c) if c.is_whitespace() => {
+                let close = start + text[start..].find('>')?;
+                return Some((&text[start..close], close + 1));
+            }
+            _ => from = start,
+        }
+    }
+    None
+}
+
+/// Reads a `<content>` body up to `</content>`, returning the text and the offset past the tag
+///
+/// CDATA sections are taken verbatim, without the newline after `<![CDATA[` and
+/// the indented line holding `]]>`; anything else is unescaped.
+fn read_content(body: &str) -> Option<(String, usize)> {
+    const OPEN: &str = "<![CDATA[";
+    const CLOSE: &str = "]]>";
+
+    if !body.trim_start().starts_with(OPEN) {
+        let end = body.find("</content>")?;
+        let text = body[..end].trim_matches(|c| c == ' ' || c == '\t');
+        let text = text.strip_prefix('\n').unwrap_or(text);
+        let text = text.trim_end_matches([' ', '\t']);
+        return Some((unescape_xml(text), end + "</content>".len()));
+    }
+
+    // Content holding `]]>` is split across several adjacent sections
+    let mut content = String::new();
+    let mut offset = body.len() - body.trim_start().len();
+    while body[offset..].starts_with(OPEN) {
+        let start = offset + OPEN.len();
+        let end = start + body[start..].find(CLOSE)?;
+        content.push_str(&body[start..end]);
+        offset = end + CLOSE.len();
+    }
+    let end = offset + body[offset..].find("</content>")?;
+
+    let content = content.strip_prefix('\n').unwrap_or(&content);
+    let trimmed = content.trim_end_matches([' ', '\t']);
+    let content = trimmed.strip_suffix('\n').unwrap_or(content);
+    Some((content.to_string(), end + "</content>".len()))
+}
+
+/// Returns the value of attribute `name` in a tag's attribute text
+fn attribute<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    let attribute = ATTRIBUTE.get_or_init(|| {
+        Regex::new(r#"([\w-]+)\s*=\s*"([^"]*)""#).expect("Attribute regex is valid")
+    });
+    attribute
+        .captures_iter(attrs)
+        .find(|captures| &captures[1] == name)
+        .map(|captures| captures.get(2).expect("Attribute has a value").as_str())
+}
+
+/// Removes the `N | ` gutter added by line numbering, if every line has one
+fn strip_gutter(content: &str) -> Option<String> {
+    static GUTTER: OnceLock<Regex> = OnceLock::new();
+    let gutter =
+        GUTTER.get_or_init(|| Regex::new(r"^ *\d* \|(?: |$)").expect("Gutter regex is valid"));
+
+    let mut stripped = String::with_capacity(content.len());
+    for line in content.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\n', '\r']);
+        let prefix = gutter.find(text)?;
+        stripped.push_str(&line[prefix.end()..]);
+    }
+    Some(stripped)
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// A file that unpacking creates or overwrites
+#[derive(Debug, Clone)]
+pub struct FileChange {
+    /// Path relative to the target root
+    pub path: PathBuf,
+    /// Current content, or `None` for a new file
+    pub old: Option<String>,
+    /// Content that will be written
+    pub new: String,
+}
+
+impl FileChange {
+    /// Returns true if writing the file would not change it
+    pub fn is_unchanged(&self) -> bool {
+        self.old.as_deref() == Some(self.new.as_str())
+    }
+
+    /// Renders the change as a unified diff against the current content
+    pub fn diff(&self) -> String {
+        let new_name = format!("b/{}", self.path.display());
+        match &self.old {
+            Some(old) => unified_diff(
+                old,
+                &self.new,
+                &format!("a/{}", self.path.display()),
+                &new_name,
+            ),
+            None => unified_diff("", &self.new, "/dev/null", &new_name),
+        }
+    }
+}
+
+/// The changes an LLM response makes to the files under a root directory
+///
+/// Every path is checked before anything is written, so a response with a path
+/// outside the root is rejected as a whole.
+#[derive(Debug)]
+pub struct Unpack {
+    root: PathBuf,
+    pub changes: Vec<FileChange>,
+    /// Files in the response that were skipped
+    pub warnings: Vec<Warning>,
+}
+
+impl Unpack {
+    /// Works out the changes `files` make under `root` without writing anything
+    ///
+    /// # Returns
+    /// * An [`OutsideRoot`](PromptPalError::OutsideRoot) error if a path is absolute
+    ///   outside `root`, climbs out with `..` or runs through a symlink leading out
+    /// * A [`Protected`](PromptPalError::Protected) error if a path is inside `.git`
+    ///   or `.promptpal`
+    /// * A [`Region`](PromptPalError::Region) error if a file appears twice, or a
+    ///   range doesn't fit the existing file
+    pub fn plan(root: &Path, files: Vec<ResponseFile>) -> Result<Self> {
+        let canonical_root = canonical_root(root)?;
+        let mut unpack = Unpack {
+            root: root.to_path_buf(),
+            changes: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        for file in files {
+            let path = confine(root, &canonical_root, &file.path)?;
+            if file.outline {
+                unpack.warnings.push(Warning::new(
+                    &path,
+                    "content is an outline and was not written",
+                ));
+                continue;
+            }
+            if !seen.insert(path.clone()) {
+                return Err(PromptPalError::Region {
+                    path,
+                    message: "the file appears more than once in the response".into(),
+                });
+            }
+
+            let target = root.join(&path);
+            let old = match fs::read_to_string(&target) {
+                Ok(old) => Some(old),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(PromptPalError::from_io(&target, e)),
+            };
+            let new = match (file.range, &old) {
+                (None, _) => file.content,
+                (Some(range), Some(old)) => {
+                    splice(old, range, &file.content).map_err(|message| PromptPalError::Region {
+                        path: path.clone(),
+                        message,
+                    })?
+                }
+                (Some(range), None) => {
+                    return Err(PromptPalError::Region {
+                        path,
+                        message: format!("lines {} replace a file that doesn't exist", range),
+                    })
+                }
+            };
+            unpack.changes.push(FileChange { path, old, new });
+        }
+        Ok(unpack)
+    }
+
+    /// Writes every changed file, backing up files it overwrites into `backup_dir`
+    ///
+    /// Existing files are written to a temporary file next to them and renamed into
+    /// place, so an interrupted unpack never leaves one half written.
+    ///
+    /// # Returns
+    /// * The paths of the backups made, relative to `backup_dir`
+    pub fn write(&self, backup_dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+        write_changes(&self.root, &self.changes, backup_dir)
+    }
+}
+
+/// Writes `changes` under `root`, as described in [`Unpack::write`]
+pub(crate) fn write_changes(
+    root: &Path,
+    changes: &[FileChange],
+    backup_dir: Option<&Path>,
+) -> Result<Vec<PathBuf>> {
+    let mut backups = Vec::new();
+    for change in changes.iter().filter(|c| !c.is_unchanged()) {
+        let target = root.join(&change.path);
+        if let (Some(dir), Some(_)) = (backup_dir, &change.old) {
+            let backup = dir.join(&change.path);
+            if let Some(parent) = backup.parent() {
+                fs::create_dir_all(parent).map_err(|e| PromptPalError::from_io(parent, e))?;
+            }
+            fs::copy(&target, &backup).map_err(|e| PromptPalError::from_io(&backup, e))?;
+            backups.push(change.path.clone());
+        }
+
+        let parent = target.parent().unwrap_or(root);
+        fs::create_dir_all(parent).map_err(|e| PromptPalError::from_io(parent, e))?;
+        let Ok(metadata) = fs::metadata(&target) else {
+            // Never follow a symlink that appeared since the path was checked
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&target)
+                .and_then(|mut file| file.write_all(change.new.as_bytes()))
+                .map_err(|e| PromptPalError::from_io(&target, e))?;
+            continue;
+        };
+        let mut temp = tempfile::NamedTempFile::new_in(parent)
+            .map_err(|e| PromptPalError::from_io(parent, e))?;
+        temp.write_all(change.new.as_bytes())
+            .map_err(|e| PromptPalError::from_io(temp.path(), e))?;
+        // Keep the permissions of the file being replaced, e.g. executable scripts
+        fs::set_permissions(temp.path(), metadata.permissions())
+            .map_err(|e| PromptPalError::from_io(temp.path(), e))?;
+        temp.persist(&target)
+            .map_err(|e| PromptPalError::from_io(&target, e.error))?;
+    }
+    Ok(backups)
+}
+
+/// Directory backups of overwritten files are kept in, one per run
+pub fn backup_dir(root: &Path) -> PathBuf {
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    root.join(STATE_DIR_NAME)
+        .join("backups")
+        .join(stamp.to_string())
+}
+
+/// Resolves the target root, which must be an existing directory
+pub(crate) fn canonical_root(root: &Path) -> Result<PathBuf> {
+    let canonical = root
+        .canonicalize()
+        .map_err(|e| PromptPalError::from_io(root, e))?;
+    if !canonical.is_dir() {
+        return Err(PromptPalError::NotADirectory {
+            path: root.to_path_buf(),
+        });
+    }
+    Ok(canonical)
+}
+
+/// Resolves `path` from a response to a path relative to `root`, refusing paths outside it
+/// and paths into the repository or promptpal's own state
+pub(crate) fn confine(root: &Path, canonical_root: &Path, path: &str) -> Result<PathBuf> {
+    let outside = || PromptPalError::OutsideRoot {
+        path: PathBuf::from(path),
+    };
+    let given = Path::new(path);
+    let relative = if given.is_absolute() {
+        given
+            .strip_prefix(canonical_root)
+            .or_else(|_| given.strip_prefix(root))
+            .map_err(|_| outside())?
+    } else {
+        given
+    };
+
+    // Resolve `.` and `..` without touching the file system
+    let mut normal = PathBuf::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => {
+                // Case-insensitive file systems would treat `.GIT` as `.git`
+                if [".git", STATE_DIR_NAME]
+                    .iter()
+                    .any(|name| part.eq_ignore_ascii_case(name))
+                {
+                    return Err(PromptPalError::Protected {
+                        path: PathBuf::from(path),
+                    });
+                }
+                normal.push(part)
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normal.pop() {
+                    return Err(outside());
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return Err(outside()),
+        }
+    }
+    if normal.as_os_str().is_empty() {
+        return Err(outside());
+    }
+
+    // Symlinks along the way may still lead out of the root. The nearest entry that
+    // exists, a symlink included, must resolve inside it; a dangling symlink doesn't
+    // resolve at all and would otherwise be followed when the file is written.
+    let existing = root
+        .join(&normal)
+        .ancestors()
+        .find(|ancestor| fs::symlink_metadata(ancestor).is_ok())
+        .and_then(|ancestor| ancestor.canonicalize().ok())
+        .ok_or_else(outside)?;
+    if !existing.starts_with(canonical_root) {
+        return Err(outside());
+    }
+    Ok(normal)
+}
+
+/// Replaces the lines `range` of `old` with `content`
+fn splice(old: &str, range: LineRange, content: &str) -> std::result::Result<String, String> {
+    let line_count = old.lines().count();
+    if range.start == 0 || range.start > range.end || range.end > line_count {
+        return Err(format!(
+            "lines {} are outside the file ({} lines)",
+            range, line_count
+        ));
+    }
+    let before = LineRange {
+        start: 1,
+        end: range.start - 1,
+    };
+    let after = LineRange {
+        start: range.end + 1,
+        end: line_count,
+    };
+
+    let mut new = String::with_capacity(old.len() + content.len());
+    if range.start > 1 {
+        new.push_str(before.slice(old));
+    }
+    new.push_str(content);
+    if range.end < line_count {
+        if !content.is_empty() && !content.ends_with('\n') {
+            new.push('\n');
+        }
+        new.push_str(after.slice(old));
+    } else if old.ends_with('\n') && !content.is_empty() && !content.ends_with('\n') {
+        new.push('\n');
+    }
+    Ok(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_response() {
+        let response = r#"Here are the changes:
+
+```xml
+<files>
+  <file>
+    <path>src/lib.rs</path>
+    <content complete="true" type="rs">
+      <![CDATA[
+pub mod cli;
+pub mod diff;
+
+      ]]>
+    </content>
+  </file>
+  <file tokens="9" range="2-3">
+    <path>notes &amp; ideas.txt</path>
+    <content start_line="2" complete="true">
+      <![CDATA[
+2 | two
+3 |
+      ]]>
+    </content>
+  </file>
+  <file><path>a.xml</path><content><![CDATA[<x>]]]]><![CDATA[></x>]]></content></file>
+</files>
+```"#;
+        let files = parse_response(response);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].path, "src/lib.rs");
+        assert_eq!(files[0].content, "pub mod cli;\npub mod diff;\n");
+        assert_eq!(files[1].path, "notes & ideas.txt");
+        assert_eq!(files[1].content, "two\n");
+        assert_eq!(files[1].range, Some(LineRange { start: 2, end: 3 }));
+        assert_eq!(files[2].content, "<x>]]></x>");
+    }
+
+    #[test]
+    fn test_paths_outside_root_are_rejected() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join("src")).unwrap();
+        let canonical = root.canonicalize().unwrap();
+
+        assert_eq!(
+            confine(&root, &canonical, "./src/../lib.rs").unwrap(),
+            PathBuf::from("lib.rs")
+        );
+        let absolute = canonical.join("src/main.rs");
+        assert_eq!(
+            confine(&root, &canonical, absolute.to_str().unwrap()).unwrap(),
+            PathBuf::from("src/main.rs")
+        );
+        for path in ["../escape.txt", "src/../../x", "/etc/passwd", ""] {
+            assert!(
+                matches!(
+                    confine(&root, &canonical, path),
+                    Err(PromptPalError::OutsideRoot { .. })
+                ),
+                "{}",
+                path
+            );
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path(), root.join("link")).unwrap();
+            assert!(confine(&root, &canonical, "link/escape.txt").is_err());
+        }
+
+        for path in [
+            ".git/config",
+            "src/.git/hooks/pre-commit",
+            ".promptpal/prompts/x.md",
+            ".GIT/HEAD",
+        ] {
+            assert!(
+                matches!(
+                    confine(&root, &canonical, path),
+                    Err(PromptPalError::Protected { .. })
+                ),
+                "{}",
+                path
+            );
+        }
+        assert!(confine(&root, &canonical, ".github/workflows/ci.yml").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dangling_symlinks_are_refused() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(outside.join("pwned.txt"), root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(outside.join("missing"), root.join("dir")).unwrap();
+
+        for path in ["link.txt", "dir/pwned.txt"] {
+            let files = parse_response(&format!(
+                "<file><path>{}</path><content>pwned</content></file>",
+                path
+            ));
+            assert!(
+                matches!(
+                    Unpack::plan(&root, files),
+                    Err(PromptPalError::OutsideRoot { .. })
+                ),
+                "{}",
+                path
+            );
+        }
+
+        // A link that appears after planning isn't followed either
+        let change = FileChange {
+            path: PathBuf::from("late.txt"),
+            old: None,
+            new: "pwned".into(),
+        };
+        std::os::unix::fs::symlink(outside.join("late.txt"), root.join("late.txt")).unwrap();
+        assert!(write_changes(&root, &[change], None).is_err());
+        assert!(fs::read_dir(&outside).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_splice_range() {
+        let old = "one\ntwo\nthree\nfour\n";
+        let range = |start, end| LineRange { start, end };
+        assert_eq!(
+            splice(old, range(2, 3), "2\n3\n").unwrap(),
+            "one\n2\n3\nfour\n"
+        );
+        assert_eq!(
+            splice(old, range(4, 4), "4").unwrap(),
+            "one\ntwo\nthree\n4\n"
+        );
+        assert_eq!(splice(old, range(1, 2), "").unwrap(), "three\nfour\n");
+        assert!(splice(old, range(3, 9), "x").is_err());
+    }
+
+    #[test]
+    fn test_plan_and_write() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("keep.txt"), "same\n").unwrap();
+        fs::write(root.join("edit.txt"), "a\nb\nc\n").unwrap();
+        let file = |path: &str, content: &str| ResponseFile {
+            path: path.into(),
+            content: content.into(),
+            range: None,
+            outline: false,
+        };
+
+        let unpack = Unpack::plan(
+            root,
+            vec![
+                file("keep.txt", "same\n"),
+                ResponseFile {
+                    range: Some(LineRange { start: 2, end: 2 }),
+                    ..file("edit.txt", "B\n")
+                },
+                file("new/dir/new.txt", "hello\n"),
+                ResponseFile {
+                    outline: true,
+                    ..file("outlined.rs", "fn a() { ... }\n")
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(unpack.changes.len(), 3);
+        assert_eq!(unpack.warnings.len(), 1);
+        assert!(unpack.changes[0].is_unchanged());
+        assert_eq!(
+            unpack.changes[1].diff(),
+            "--- a/edit.txt\n+++ b/edit.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+
+        let backups = root.join("backups");
+        assert_eq!(
+            unpack.write(Some(&backups)).unwrap(),
+            vec![PathBuf::from("edit.txt")]
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("edit.txt")).unwrap(),
+            "a\nB\nc\n"
+        );
+        assert_eq!(
+            fs::read_to_string(backups.join("edit.txt")).unwrap(),
+            "a\nb\nc\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("new/dir/new.txt")).unwrap(),
+            "hello\n"
+        );
+
+        let duplicate = Unpack::plan(root, vec![file("x", "1"), file("./x", "2")]);
+        assert!(matches!(duplicate, Err(PromptPalError::Region { .. })));
+    }
+}
@@ -1,13 +1,166 @@
-use std::path::PathBuf;
+use crate::error::{PromptPalError, Result, Warning};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use walkdir::WalkDir;
 
+/// Directory promptpal keeps its own state in (backups, caches), never scanned
+pub const STATE_DIR_NAME: &str = ".promptpal";
+
+/// File names that are never included unless sensitive files are explicitly allowed
+const SENSITIVE_FILE_NAMES: &[&str] = &[
+    ".env",
+    ".env.*",
+    "*.env",
+    "id_rsa*",
+    "id_dsa*",
+    "id_ecdsa*",
+    "id_ed25519*",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "*.jks",
+    "*.keystore",
+    "*.kdbx",
+    "credentials.json",
+    "credentials",
+    "service-account*.json",
+    ".netrc",
+    ".pgpass",
+    ".htpasswd",
+    ".git-credentials",
+    ".dockercfg",
+];
+
+/// Config files that are only sensitive when they contain credentials
+const AUTH_CONFIG_FILES: &[(&str, &[&str])] = &[
+    (".npmrc", &["_auth", "_password"]),
+    (".yarnrc.yml", &["npmAuthToken", "npmAuthIdent"]),
+    (".pypirc", &["password"]),
+];
+
+/// Include and exclude glob patterns, matched against paths relative to the scan root
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    narrow: Vec<GlobSet>,
+    exclude: Option<GlobSet>,
+    allow_sensitive: bool,
+}
+
+/// Files found by a scan, plus what was left out along the way
+#[derive(Debug, Clone, Default)]
+pub struct Scan {
+    /// Files that passed the filter, in walk order
+    pub files: Vec<PathBuf>,
+    /// Files skipped because they look like they hold credentials
+    pub sensitive: Vec<PathBuf>,
+    /// Entries that couldn't be read
+    pub warnings: Vec<Warning>,
+}
+
+impl FileFilter {
+    /// Creates a filter; an empty include list includes every file
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(FileFilter {
+            include: Self::glob_set(include)?,
+            narrow: Vec::new(),
+            exclude: Self::glob_set(exclude)?,
+            allow_sensitive: false,
+        })
+    }
+
+    /// Also requires files to match one of `patterns`, on top of the include globs
+    pub fn narrow(mut self, patterns: &[String]) -> Result<Self> {
+        self.narrow.extend(Self::glob_set(patterns)?);
+        Ok(self)
+    }
+
+    /// Includes files on the sensitive denylist (`.env`, private keys, credentials, ...)
+    pub fn allow_sensitive(mut self, allow: bool) -> Self {
+        self.allow_sensitive = allow;
+        self
+    }
+
+    /// Returns true if a file at `relative` passes the filter
+    pub fn is_match(&self, relative: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|set| set.is_match(relative))
+            && self.narrow.iter().all(|set| set.is_match(relative))
+            && !self.is_excluded(relative)
+    }
+
+    /// Returns true if `relative` matches an exclude pattern
+    pub fn is_excluded(&self, relative: &Path) -> bool {
+        self.exclude
+            .as_ref()
+            .is_some_and(|set| set.is_match(relative))
+    }
+
+    fn glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = Glob::new(pattern).map_err(|e| PromptPalError::InvalidPattern {
+                pattern: pattern.clone(),
+                message: e.kind().to_string(),
+            })?;
+            builder.add(glob);
+        }
+        builder
+            .build()
+            .map(Some)
+            .map_err(|e| PromptPalError::InvalidPattern {
+                pattern: patterns.join(", "),
+                message: e.to_string(),
+            })
+    }
+}
+
+/// Returns true if a file looks like it holds credentials and should be skipped by default
+///
+/// Matches the file name against a denylist of secret-bearing files, and checks
+/// package manager configs such as `.npmrc` for auth entries.
+pub fn is_sensitive(path: &Path) -> bool {
+    static SENSITIVE: OnceLock<GlobSet> = OnceLock::new();
+    let sensitive = SENSITIVE.get_or_init(|| {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in SENSITIVE_FILE_NAMES {
+            builder.add(Glob::new(pattern).expect("Sensitive file pattern is valid"));
+        }
+        builder.build().expect("Sensitive file patterns are valid")
+    });
+
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    if sensitive.is_match(name) {
+        return true;
+    }
+
+    AUTH_CONFIG_FILES
+        .iter()
+        .find(|(config, _)| name == *config)
+        .is_some_and(|(_, keys)| {
+            fs::read_to_string(path)
+                .map(|content| keys.iter().any(|key| content.contains(key)))
+                .unwrap_or(false)
+        })
+}
+
 /// Scans a directory and returns a list of all files found.
 ///
 /// # Arguments
 /// * `target_dir` - The directory path to scan
 ///
 /// # Returns
-/// * `Vec<PathBuf>` - A vector of paths to all files found
+/// * `Vec<PathBuf>` - A vector of paths to all files found; entries that can't be read
+///   and [sensitive files](is_sensitive) are skipped
 ///
 /// # Example
 /// ```rust
@@ -21,37 +174,62 @@ use walkdir::WalkDir;
 /// # Ok(())
 /// # }
 /// ```
-#[allow(dead_code)]
-pub fn scan_directory(target_dir: impl Into<PathBuf>) -> std::io::Result<Vec<PathBuf>> {
+pub fn scan_directory(target_dir: impl Into<PathBuf>) -> Result<Vec<PathBuf>> {
+    scan_directory_filtered(target_dir, &FileFilter::default()).map(|scan| scan.files)
+}
+
+/// Scans a directory like [`scan_directory`], keeping only files that pass `filter`.
+///
+/// Directories matching an exclude pattern, and the [state directory](STATE_DIR_NAME),
+/// are not descended into. Entries that
+/// can't be read are reported as warnings instead of failing the scan, and
+/// sensitive files are listed separately unless the filter allows them.
+pub fn scan_directory_filtered(
+    target_dir: impl Into<PathBuf>,
+    filter: &FileFilter,
+) -> Result<Scan> {
     let target_dir = target_dir.into();
-    let mut files = Vec::new();
+    let mut scan = Scan::default();
 
     // Validate that the directory exists and is a directory
     if !target_dir.exists() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "Directory not found",
-        ));
+        return Err(PromptPalError::MissingRoot { path: target_dir });
     }
     if !target_dir.is_dir() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Path is not a directory",
-        ));
+        return Err(PromptPalError::NotADirectory { path: target_dir });
     }
 
     // Walk the directory tree
+    let relative = |path: &Path| path.strip_prefix(&target_dir).unwrap_or(path).to_path_buf();
     for entry in WalkDir::new(&target_dir)
         .follow_links(true)
+        .sort_by_file_name()
         .into_iter()
-        .filter_map(|e| e.ok())
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !(e.file_type().is_dir() && e.file_name() == STATE_DIR_NAME
+                    || filter.is_excluded(&relative(e.path())))
+        })
     {
-        if entry.file_type().is_file() {
-            files.push(entry.path().to_path_buf());
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().unwrap_or(&target_dir).to_path_buf();
+                scan.warnings.push(Warning::new(path, e.to_string()));
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() || !filter.is_match(&relative(entry.path())) {
+            continue;
+        }
+        if !filter.allow_sensitive && is_sensitive(entry.path()) {
+            scan.sensitive.push(entry.path().to_path_buf());
+        } else {
+            scan.files.push(entry.path().to_path_buf());
         }
     }
 
-    Ok(files)
+    Ok(scan)
 }
 
 #[cfg(test)]
@@ -87,10 +265,68 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_scan_directory_filtered() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+
+        File::create(temp_path.join("main.rs"))?;
+        File::create(temp_path.join("notes.md"))?;
+        fs::create_dir(temp_path.join("target"))?;
+        File::create(temp_path.join("target").join("build.rs"))?;
+        fs::create_dir(temp_path.join(STATE_DIR_NAME))?;
+        File::create(temp_path.join(STATE_DIR_NAME).join("backup.rs"))?;
+
+        let filter = FileFilter::new(&["**/*.rs".to_string()], &["target".to_string()])?;
+        let scan = scan_directory_filtered(temp_path, &filter)?;
+
+        assert_eq!(scan.files, vec![temp_path.join("main.rs")]);
+        assert!(scan.warnings.is_empty());
+        assert!(FileFilter::new(&["[".to_string()], &[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sensitive_files_are_skipped() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+
+        File::create(temp_path.join("main.rs"))?;
+        File::create(temp_path.join(".env.local"))?;
+        File::create(temp_path.join("id_rsa"))?;
+        File::create(temp_path.join("server.pem"))?;
+        fs::write(
+            temp_path.join(".npmrc"),
+            "//registry.npmjs.org/:_authToken=abc\n",
+        )?;
+        fs::create_dir(temp_path.join("web"))?;
+        fs::write(temp_path.join("web").join(".npmrc"), "save-exact=true\n")?;
+
+        let scan = scan_directory_filtered(temp_path, &FileFilter::default())?;
+        assert_eq!(
+            scan.files,
+            vec![
+                temp_path.join("main.rs"),
+                temp_path.join("web").join(".npmrc")
+            ]
+        );
+        assert_eq!(scan.sensitive.len(), 4);
+
+        let scan =
+            scan_directory_filtered(temp_path, &FileFilter::default().allow_sensitive(true))?;
+        assert_eq!(scan.files.len(), 6);
+        assert!(scan.sensitive.is_empty());
+
+        Ok(())
+    }
+
     #[test]
     fn test_scan_nonexistent_directory() {
         let result = scan_directory("nonexistent_directory");
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        assert!(matches!(
+            result,
+            Err(PromptPalError::MissingRoot { path }) if path == Path::new("nonexistent_directory")
+        ));
     }
 }
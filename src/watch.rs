@@ -0,0 +1,365 @@
+use crate::builder::{PromptBuilder, PromptReport};
+use crate::cache::Cache;
+use crate::error::{PromptPalError, Result};
+use crate::walkdir::{FileFilter, STATE_DIR_NAME};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::io::BufWriter;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for more changes after the first one before rebuilding, so an
+/// editor's save or a `git checkout` causes one rebuild instead of dozens
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Rebuilds a prompt into an output file whenever the files it's built from change
+///
+/// Token counts are kept between builds, in the builder's [cache](PromptBuilder::cache)
+/// or else in memory, so only files that changed are tokenized again. The output is
+/// written to a temporary file next to it and renamed into place, so readers never
+/// see a half-written prompt.
+///
+/// # Example
+/// ```no_run
+/// use promptpal::{watch::PromptWatcher, PromptBuilder};
+/// # fn main() -> promptpal::error::Result<()> {
+/// let mut watcher = PromptWatcher::new(PromptBuilder::new().root("src"), "prompt.xml")?;
+/// watcher.build()?;
+/// loop {
+///     watcher.wait()?;
+///     let rebuild = watcher.build()?;
+///     println!("{:+} tokens", rebuild.delta());
+/// }
+/// # }
+/// ```
+pub struct PromptWatcher {
+    builder: PromptBuilder,
+    output: PathBuf,
+    filter: FileFilter,
+    /// Canonical path of each root, which event paths start with, and the root as given
+    roots: Vec<(PathBuf, PathBuf)>,
+    /// Canonical directory and name of the output, whose changes are our own
+    output_dir: PathBuf,
+    output_name: OsString,
+    /// Tokens per included file in the last build
+    previous: Option<BTreeMap<PathBuf, usize>>,
+    /// Canonical paths of the files the last build scanned, to notice them being removed
+    known: HashSet<PathBuf>,
+    events: Receiver<notify::Result<Event>>,
+    _watcher: RecommendedWatcher,
+}
+
+/// The result of rebuilding a prompt
+#[derive(Debug, Clone)]
+pub struct Rebuild {
+    /// Report of the new prompt
+    pub report: PromptReport,
+    /// Included files whose token count changed, or that were added or removed, in path order
+    pub changes: Vec<TokenChange>,
+}
+
+impl Rebuild {
+    /// Change in file content tokens since the previous build
+    pub fn delta(&self) -> isize {
+        self.changes.iter().map(TokenChange::delta).sum()
+    }
+}
+
+/// How the tokens a file contributes to the prompt changed between two builds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenChange {
+    pub path: PathBuf,
+    /// Tokens before, or `None` if the file wasn't included
+    pub before: Option<usize>,
+    /// Tokens after, or `None` if the file is no longer included
+    pub after: Option<usize>,
+}
+
+impl TokenChange {
+    pub fn delta(&self) -> isize {
+        self.after.unwrap_or_default() as isize - self.before.unwrap_or_default() as isize
+    }
+}
+
+impl PromptWatcher {
+    /// Starts watching the builder's roots; nothing is built until [`build`](Self::build)
+    pub fn new(builder: PromptBuilder, output: impl Into<PathBuf>) -> Result<Self> {
+        let output = output.into();
+        let parent = match output.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let output_dir = parent
+            .canonicalize()
+            .map_err(|e| PromptPalError::from_io(parent, e))?;
+        let output_name = output.file_name().map(OsString::from).ok_or_else(|| {
+            PromptPalError::from_io(
+                &output,
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file name"),
+            )
+        })?;
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(sender).map_err(|e| watch_error(Path::new("."), e))?;
+        let mut roots = Vec::new();
+        for root in builder.roots() {
+            let canonical = root
+                .canonicalize()
+                .map_err(|e| PromptPalError::from_io(root, e))?;
+            watcher
+                .watch(&canonical, RecursiveMode::Recursive)
+                .map_err(|e| watch_error(root, e))?;
+            roots.push((canonical, root.clone()));
+        }
+
+        let mut builder = builder;
+        if !builder.has_cache() {
+            builder = builder.cache(Arc::new(Cache::in_memory()));
+        }
+        // An output under a root would otherwise end up in the next prompt
+        for (canonical, _) in &roots {
+            if let Ok(relative) = output_dir.strip_prefix(canonical) {
+                let escape = |path: PathBuf| globset::escape(&path.to_string_lossy());
+                let temp = relative.join(temp_prefix(&output_name));
+                builder = builder
+                    .exclude(escape(relative.join(&output_name)))
+                    .exclude(format!("{}*.tmp", escape(temp)));
+            }
+        }
+        let filter = builder.file_filter()?;
+
+        Ok(PromptWatcher {
+            builder,
+            output,
+            filter,
+            roots,
+            output_dir,
+            output_name,
+            previous: None,
+            known: HashSet::new(),
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Builds the prompt and atomically replaces the output with it
+    ///
+    /// If the build fails, the output is left as it was.
+    pub fn build(&mut self) -> Result<Rebuild> {
+        let mut temp = tempfile::Builder::new()
+            .prefix(&self.temp_prefix())
+            .suffix(".tmp")
+            .tempfile_in(&self.output_dir)
+            .map_err(|e| PromptPalError::from_io(&self.output_dir, e))?;
+        let report = self.builder.write_to(BufWriter::new(temp.as_file_mut()))?;
+        // Keep the permissions of the file being replaced, like an editor would
+        if let Ok(metadata) = std::fs::metadata(&self.output) {
+            let _ = temp.as_file().set_permissions(metadata.permissions());
+        }
+        temp.persist(&self.output)
+            .map_err(|e| PromptPalError::from_io(&self.output, e.error))?;
+
+        let tokens: BTreeMap<_, _> = report
+            .files
+            .iter()
+            .map(|file| (file.path.clone(), file.tokens))
+            .collect();
+        let changes = match &self.previous {
+            Some(previous) => token_changes(previous, &tokens),
+            None => Vec::new(),
+        };
+        self.known = report
+            .files
+            .iter()
+            .chain(&report.skipped)
+            .filter_map(|file| self.canonical(&file.path))
+            .collect();
+        self.previous = Some(tokens);
+        Ok(Rebuild { report, changes })
+    }
+
+    /// Blocks until files the prompt could be built from change, then returns them
+    ///
+    /// Changes that arrive in quick succession are returned together.
+    pub fn wait(&self) -> Result<Vec<PathBuf>> {
+        let mut changed = BTreeSet::new();
+        while changed.is_empty() {
+            let event = self.events.recv().map_err(|_| self.stopped())?;
+            self.collect(event, &mut changed)?;
+        }
+        loop {
+            match self.events.recv_timeout(DEBOUNCE) {
+                Ok(event) => self.collect(event, &mut changed)?,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Err(self.stopped()),
+            }
+        }
+        Ok(changed.into_iter().collect())
+    }
+
+    /// Adds the paths of `event` that matter to the prompt to `changed`, as the builder
+    /// would name them
+    fn collect(&self, event: notify::Result<Event>, changed: &mut BTreeSet<PathBuf>) -> Result<()> {
+        let event = event.map_err(|e| watch_error(&self.output_dir, e))?;
+        if matches!(event.kind, EventKind::Access(_)) {
+            return Ok(());
+        }
+        for path in event.paths {
+            if let Some(path) = self.relevant(&path) {
+                changed.insert(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `path` under its root as given, if a change to it can change the prompt
+    fn relevant(&self, path: &Path) -> Option<PathBuf> {
+        if self.is_output(path) {
+            return None;
+        }
+        let (canonical_root, root) = self
+            .roots
+            .iter()
+            .find(|(canonical, _)| path.starts_with(canonical))?;
+        let relative = path.strip_prefix(canonical_root).ok()?;
+        // Excluded directories and the state directory are never scanned
+        let mut ancestor = PathBuf::new();
+        for component in relative.components() {
+            if component == Component::Normal(STATE_DIR_NAME.as_ref()) {
+                return None;
+            }
+            ancestor.push(component);
+            if self.filter.is_excluded(&ancestor) {
+                return None;
+            }
+        }
+        let scanned = self.filter.is_match(relative)
+            || path.is_dir()
+            || self.known.iter().any(|file| file.starts_with(path));
+        scanned.then(|| root.join(relative))
+    }
+
+    /// Returns true for the output file and the temporary files it's written through
+    fn is_output(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+        path.parent() == Some(self.output_dir.as_path())
+            && (name == self.output_name || name.to_string_lossy().starts_with(&self.temp_prefix()))
+    }
+
+    fn temp_prefix(&self) -> String {
+        temp_prefix(&self.output_name)
+    }
+
+    /// Maps a path from the builder to the canonical path events use
+    fn canonical(&self, path: &Path) -> Option<PathBuf> {
+        self.roots
+            .iter()
+            .find_map(|(canonical, root)| Some(canonical.join(path.strip_prefix(root).ok()?)))
+    }
+
+    fn stopped(&self) -> PromptPalError {
+        PromptPalError::Watch {
+            path: self
+                .roots
+                .first()
+                .map(|(_, root)| root.clone())
+                .unwrap_or_default(),
+            message: "the file watcher stopped".into(),
+        }
+    }
+}
+
+/// Start of the names of the temporary files an output named `name` is written through
+fn temp_prefix(name: &OsStr) -> String {
+    format!(".{}.", name.to_string_lossy())
+}
+
+/// Compares the tokens per file of two builds
+fn token_changes(
+    before: &BTreeMap<PathBuf, usize>,
+    after: &BTreeMap<PathBuf, usize>,
+) -> Vec<TokenChange> {
+    let paths: BTreeSet<_> = before.keys().chain(after.keys()).collect();
+    paths
+        .into_iter()
+        .map(|path| TokenChange {
+            path: path.clone(),
+            before: before.get(path).copied(),
+            after: after.get(path).copied(),
+        })
+        .filter(|change| change.before != change.after)
+        .collect()
+}
+
+fn watch_error(path: &Path, mut error: notify::Error) -> PromptPalError {
+    let path = error.paths.pop().unwrap_or_else(|| path.to_path_buf());
+    error.paths.clear();
+    PromptPalError::Watch {
+        path,
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rebuild_reports_token_changes() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("src");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+        fs::write(root.join("b.rs"), "fn b() {}\n").unwrap();
+        // Written under the root, which must not put the prompt into itself
+        fs::create_dir(root.join("out")).unwrap();
+        let output = root.join("out/prompt.xml");
+
+        let mut watcher = PromptWatcher::new(PromptBuilder::new().root(&root), &output)?;
+        let first = watcher.build()?;
+        assert_eq!(first.report.files.len(), 2);
+        assert!(first.changes.is_empty());
+        assert!(fs::read_to_string(&output).unwrap().contains("fn b() {}"));
+
+        fs::write(root.join("a.rs"), "fn a() { println!(\"changed\"); }\n").unwrap();
+        fs::remove_file(root.join("b.rs")).unwrap();
+        fs::write(root.join("c.rs"), "fn c() {}\n").unwrap();
+        let rebuild = watcher.build()?;
+        let summary: Vec<_> = rebuild
+            .changes
+            .iter()
+            .map(|c| (c.path.file_name().unwrap().to_owned(), c.before, c.after))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("a.rs".into(), Some(4), Some(9)),
+                ("b.rs".into(), Some(4), None),
+                ("c.rs".into(), None, Some(4)),
+            ]
+        );
+        assert_eq!(rebuild.delta(), 5);
+        let written = fs::read_to_string(&output).unwrap();
+        assert!(written.contains("changed") && !written.contains("fn b() {}"));
+
+        // The output and the state directory never count as changes
+        let canonical_root = root.canonicalize().unwrap();
+        assert_eq!(
+            watcher.relevant(&canonical_root.join("a.rs")),
+            Some(root.join("a.rs"))
+        );
+        assert_eq!(watcher.relevant(&canonical_root.join(".promptpal/x")), None);
+        let output_dir = canonical_root.join("out");
+        assert!(watcher.is_output(&output_dir.join("prompt.xml")));
+        assert!(watcher.is_output(&output_dir.join(".prompt.xml.a1b2.tmp")));
+        Ok(())
+    }
+}
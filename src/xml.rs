@@ -1,149 +1,142 @@
-use crate::fileinfo::FileInfo;
-use std::collections::HashMap;
+use crate::fileinfo::{ContentMode, FileInfo};
+use crate::imports::ImportGraph;
+use crate::render::RenderOptions;
+use crate::tree::FileNode;
+use std::io::{self, Write};
 use std::path::Path;
 
-#[derive(Default)]
-struct FileNode {
-    name: String,
-    file_info: Option<FileInfo>,
-    children: HashMap<String, FileNode>,
-}
-
 pub struct XmlGenerator;
 
 impl XmlGenerator {
     pub fn generate(files: &[FileInfo], instructions: &str) -> String {
-        let root = Self::build_tree(files);
-        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<files>\n");
+        Self::generate_with_options(files, instructions, &RenderOptions::default())
+    }
+
+    pub fn generate_with_options(
+        files: &[FileInfo],
+        instructions: &str,
+        options: &RenderOptions,
+    ) -> String {
+        let mut xml = Vec::new();
+        Self::write(&mut xml, files, instructions, options)
+            .expect("Failed to generate XML from loaded content");
+        String::from_utf8(xml).expect("Generated XML is valid UTF-8")
+    }
+
+    /// Writes the XML document incrementally to `writer`
+    ///
+    /// With `options.lazy_content`, each file whose content isn't loaded is read just
+    /// before its `<file>` element is written, so only one file is held in memory at a time.
+    pub fn write<W: Write>(
+        writer: &mut W,
+        files: &[FileInfo],
+        instructions: &str,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        let root = FileNode::build(files, options);
+        writer.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<files>\n")?;
 
         // Add user instructions
-        xml.push_str("  <instructions>\n    <![CDATA[\n");
-        xml.push_str(instructions);
-        xml.push_str("\n    ]]>\n  </instructions>\n\n");
+        writer.write_all(b"  <instructions>\n    <![CDATA[\n")?;
+        writer.write_all(instructions.as_bytes())?;
+        writer.write_all(b"\n    ]]>\n  </instructions>\n\n")?;
 
         // Generate and add the tree visualization
-        let tree_view = Self::generate_tree_view(&root);
-        xml.push_str("  <tree_view>\n");
+        let tree_view = root.tree_view(options.annotate_tree);
+        writer.write_all(b"  <tree_view>\n")?;
         for line in tree_view.lines() {
-            xml.push_str(&format!("    {}\n", Self::escape_xml(line)));
+            writeln!(writer, "    {}", Self::escape_xml(line))?;
         }
-        xml.push_str("  </tree_view>\n\n");
-
-        Self::generate_xml_from_tree(&root, &mut xml, 1);
-        xml.push_str("</files>");
-        xml
-    }
+        writer.write_all(b"  </tree_view>\n\n")?;
 
-    fn generate_tree_view(node: &FileNode) -> String {
-        let mut result = String::new();
-        Self::generate_tree_view_recursive(node, "", "", &mut result);
-        result
-    }
-
-    fn generate_tree_view_recursive(
-        node: &FileNode,
-        prefix: &str,
-        name: &str,
-        result: &mut String,
-    ) {
-        if !name.is_empty() {
-            result.push_str(&format!("{}{}\n", prefix, name));
+        if let Some(graph) = &options.dependencies {
+            Self::write_dependencies(writer, graph, options)?;
         }
 
-        let mut sorted_children: Vec<_> = node.children.values().collect();
-        sorted_children.sort_by(|a, b| a.name.cmp(&b.name));
-
-        for (i, child) in sorted_children.iter().enumerate() {
-            let is_last = i == sorted_children.len() - 1;
-            let (next_prefix, pointer) = if is_last {
-                (format!("{}    ", prefix), "└── ")
-            } else {
-                (format!("{}│   ", prefix), "├── ")
-            };
-
-            let display_name = if child.file_info.is_some() {
-                &child.name
-            } else {
-                &format!("{}/", child.name)
-            };
-
-            Self::generate_tree_view_recursive(
-                child,
-                &next_prefix,
-                &format!("{}{}", pointer, display_name),
-                result,
-            );
-        }
+        Self::write_xml_from_tree(&root, writer, 1, options)?;
+        writer.write_all(b"</files>")?;
+        writer.flush()
     }
 
-    fn build_tree(files: &[FileInfo]) -> FileNode {
-        let mut root = FileNode::default();
-
-        for file in files {
-            let path_components: Vec<_> = file
-                .path
-                .components()
-                .map(|c| c.as_os_str().to_string_lossy().into_owned())
-                .collect();
-            let mut current = &mut root;
-
-            // Create the path hierarchy
-            for (i, component) in path_components.iter().enumerate() {
-                let is_last = i == path_components.len() - 1;
-                current = current
-                    .children
-                    .entry(component.clone())
-                    .or_insert_with(|| FileNode {
-                        name: component.clone(),
-                        file_info: if is_last { Some(file.clone()) } else { None },
-                        children: HashMap::new(),
-                    });
+    /// Writes the import graph as `<file>` elements listing each file's `<import>`s
+    fn write_dependencies<W: Write>(
+        writer: &mut W,
+        graph: &ImportGraph,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        let path = |path: &Path| Self::escape_xml(&options.display_path(path).to_string_lossy());
+
+        writer.write_all(b"  <dependencies>\n")?;
+        for file in &graph.files {
+            let imports = graph.edges.get(file).map(Vec::as_slice).unwrap_or_default();
+            if imports.is_empty() {
+                writeln!(writer, "    <file path=\"{}\"/>", path(file))?;
+                continue;
             }
+            writeln!(writer, "    <file path=\"{}\">", path(file))?;
+            for import in imports {
+                writeln!(writer, "      <import path=\"{}\"/>", path(import))?;
+            }
+            writer.write_all(b"    </file>\n")?;
         }
-
-        root
+        writer.write_all(b"  </dependencies>\n\n")
     }
 
-    fn generate_xml_from_tree(node: &FileNode, xml: &mut String, depth: usize) {
+    fn write_xml_from_tree<W: Write>(
+        node: &FileNode,
+        writer: &mut W,
+        depth: usize,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
         let indent = "  ".repeat(depth);
 
-        if let Some(file_info) = &node.file_info {
-            xml.push_str(&format!("{}<file>\n", indent));
-            xml.push_str(&format!(
-                "{}  <path>{}</path>\n",
+        if let Some(file_info) = node.file_info {
+            match file_info.range {
+                Some(range) => writeln!(
+                    writer,
+                    "{}<file tokens=\"{}\" range=\"{}\">",
+                    indent, node.tokens, range
+                )?,
+                None => writeln!(writer, "{}<file tokens=\"{}\">", indent, node.tokens)?,
+            }
+            writeln!(
+                writer,
+                "{}  <path>{}</path>",
                 indent,
-                file_info.path.display()
-            ));
-            xml.push_str(&format!("{}  <size>{}</size>\n", indent, file_info.size));
+                options.display_path(&file_info.path).display()
+            )?;
+            writeln!(writer, "{}  <size>{}</size>", indent, file_info.size)?;
 
+            let file_info = options.load_content(file_info)?;
             if let Some(content) = &file_info.content {
-                let file_type = Self::detect_file_type(&file_info.path);
-                xml.push_str(&format!(
-                    "{}  <content complete=\"{}\" type=\"{}\">\n{}    <![CDATA[\n",
-                    indent, file_info.is_content_complete, file_type, indent
-                ));
+                let file_type = file_info.file_type();
+                let mode = match file_info.mode {
+                    ContentMode::Full => "",
+                    ContentMode::Outline => "mode=\"outline\" ",
+                };
+                let start_line = if options.line_numbers || file_info.range.is_some() {
+                    format!("start_line=\"{}\" ", file_info.start_line)
+                } else {
+                    String::new()
+                };
+                write!(
+                    writer,
+                    "{}  <content {}{}complete=\"{}\" type=\"{}\">\n{}    <![CDATA[\n",
+                    indent, mode, start_line, file_info.is_content_complete, file_type, indent
+                )?;
 
                 // Add content without indentation
-                xml.push_str(content);
-                xml.push_str(&format!("\n{}    ]]>\n{}  </content>\n", indent, indent));
+                writer.write_all(content.as_bytes())?;
+                write!(writer, "\n{}    ]]>\n{}  </content>\n", indent, indent)?;
             }
-            xml.push_str(&format!("{}</file>\n", indent));
+            writeln!(writer, "{}</file>", indent)?;
         }
 
         // Sort children for consistent output
-        let mut sorted_children: Vec<_> = node.children.values().collect();
-        sorted_children.sort_by(|a, b| a.name.cmp(&b.name));
-
-        for child in sorted_children {
-            Self::generate_xml_from_tree(child, xml, depth);
+        for child in node.sorted_children() {
+            Self::write_xml_from_tree(child, writer, depth, options)?;
         }
-    }
-
-    fn detect_file_type(path: &Path) -> String {
-        path.extension()
-            .and_then(|ext| ext.to_str())
-            .map(|ext| ext.to_lowercase())
-            .unwrap_or_else(|| "text".to_string())
+        Ok(())
     }
 
     fn escape_xml(text: &str) -> String {
@@ -154,3 +147,84 @@ impl XmlGenerator {
             .replace('\'', "&apos;")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::count_tokens;
+    use std::path::PathBuf;
+
+    fn file(path: &str, content: &str) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from(path),
+            size: content.len() as u64,
+            content: Some(content.to_string()),
+            is_content_complete: true,
+            tokens: None,
+            mode: ContentMode::Full,
+            start_line: 1,
+            range: None,
+        }
+    }
+
+    #[test]
+    fn test_annotated_tree_view() {
+        let files = vec![
+            file("src/main.rs", "fn main() {}"),
+            file("src/lib.rs", "pub mod cli;"),
+            file("README.md", "Hello"),
+        ];
+
+        let plain = XmlGenerator::generate(&files, "");
+        assert!(plain.contains("├── README.md\n"));
+        assert!(!plain.contains("tokens,"));
+
+        let options = RenderOptions {
+            annotate_tree: true,
+            ..RenderOptions::default()
+        };
+        let xml = XmlGenerator::generate_with_options(&files, "", &options);
+        let main_tokens = count_tokens("fn main() {}", None).0;
+        let lib_tokens = count_tokens("pub mod cli;", None).0;
+
+        assert!(xml.contains("├── README.md (1 tokens, 5 B)"));
+        assert!(xml.contains(&format!(
+            "└── src/ ({} tokens, 24 B)",
+            main_tokens + lib_tokens
+        )));
+        assert!(xml.contains(&format!("    └── main.rs ({} tokens, 12 B)", main_tokens)));
+    }
+
+    #[test]
+    fn test_write_loads_content_lazily() -> std::io::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("lazy.txt");
+        std::fs::write(&path, "loaded on demand")?;
+
+        let mut info = FileInfo::with_preview(&path, 0)?;
+        info.tokens = Some(3);
+        let options = RenderOptions {
+            lazy_content: true,
+            ..RenderOptions::default()
+        };
+
+        let mut out = Vec::new();
+        XmlGenerator::write(&mut out, &[info.clone()], "", &options)?;
+        let xml = String::from_utf8(out).unwrap();
+        assert!(xml.contains("<file tokens=\"3\">"));
+        assert!(xml.contains("loaded on demand"));
+
+        // Without lazy loading, files without content are written without a content block
+        let xml = XmlGenerator::generate(&[info], "");
+        assert!(!xml.contains("loaded on demand"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_tokens_attribute() {
+        let files = vec![file("a.txt", "Hello, world!")];
+        let xml = XmlGenerator::generate(&files, "");
+        assert!(xml.contains("<file tokens=\"4\">"));
+    }
+}
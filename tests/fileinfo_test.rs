@@ -50,10 +50,11 @@ fn test_gather_file_info() -> std::io::Result<()> {
     create_test_file(&subdir, "file3.txt", "Content 3")?;
 
     // Gather file info with previews
-    let file_infos = gather_file_info(temp_path, 5)?;
+    let (file_infos, warnings) = gather_file_info(temp_path, 5)?;
 
     // Verify we found all files
     assert_eq!(file_infos.len(), 3);
+    assert!(warnings.is_empty());
 
     // Verify each file has correct metadata
     for info in file_infos {